@@ -12,9 +12,11 @@ import {
   Counter,
   decodeCounter,
   decodeSettings,
-  encodeCounter,
+  DEFAULT_NAMESPACE,
+  encodeCreateCounterIx,
   encodeDecIx,
   encodeIncIx,
+  encodeInitializeSettingsIx,
   encodeUpdateSettingsIx,
   Settings,
 } from "./serialization"
@@ -27,6 +29,14 @@ function delay(ms: number) {
 export class App {
   static counterSeed = "counter"
   static settingsSeed = "settings"
+  static treasurySeed = "treasury"
+  static historySeed = "history"
+  static statsSeed = "stats"
+  static leaderboardSeed = "leaderboard"
+  static rateLimitSeed = "rate_limit"
+  static bpfLoaderUpgradeableProgramId = new PublicKey(
+    "BPFLoaderUpgradeab1e11111111111111111111111"
+  )
 
   adminKeypair: Keypair
   userKeypair: Keypair
@@ -34,6 +44,11 @@ export class App {
   connection: Connection
   counterPubkey: PublicKey
   settingsPubkey: PublicKey
+  treasuryPubkey: PublicKey
+  historyPubkey: PublicKey
+  statsPubkey: PublicKey
+  leaderboardPubkey: PublicKey
+  rateLimitPubkey: PublicKey
 
   constructor() {
     this.adminKeypair = App.readKeypairFromPath(__dirname + "/../../localnet/admin.json")
@@ -42,20 +57,60 @@ export class App {
     this.connection = new Connection("http://localhost:8899", "confirmed")
     this.counterPubkey = new PublicKey(0)
     this.settingsPubkey = new PublicKey(0)
+    this.treasuryPubkey = new PublicKey(0)
+    this.historyPubkey = new PublicKey(0)
+    this.statsPubkey = new PublicKey(0)
+    this.leaderboardPubkey = new PublicKey(0)
+    this.rateLimitPubkey = new PublicKey(0)
   }
 
   async init() {
-    this.counterPubkey = await PublicKey.createWithSeed(
-      this.userKeypair.publicKey,
-      App.counterSeed,
-      this.programKeypair.publicKey
-    )
+    this.counterPubkey = (
+      await PublicKey.findProgramAddress(
+        [Buffer.from(App.counterSeed, "utf-8"), this.userKeypair.publicKey.toBuffer()],
+        this.programKeypair.publicKey
+      )
+    )[0]
     this.settingsPubkey = (
       await PublicKey.findProgramAddress(
         [Buffer.from(App.settingsSeed, "utf-8")],
         this.programKeypair.publicKey
       )
     )[0]
+    this.treasuryPubkey = (
+      await PublicKey.findProgramAddress(
+        [Buffer.from(App.treasurySeed, "utf-8"), DEFAULT_NAMESPACE],
+        this.programKeypair.publicKey
+      )
+    )[0]
+    this.historyPubkey = (
+      await PublicKey.findProgramAddress(
+        [Buffer.from(App.historySeed, "utf-8"), this.counterPubkey.toBuffer()],
+        this.programKeypair.publicKey
+      )
+    )[0]
+    this.statsPubkey = (
+      await PublicKey.findProgramAddress(
+        [Buffer.from(App.statsSeed, "utf-8")],
+        this.programKeypair.publicKey
+      )
+    )[0]
+    this.leaderboardPubkey = (
+      await PublicKey.findProgramAddress(
+        [Buffer.from(App.leaderboardSeed, "utf-8"), DEFAULT_NAMESPACE],
+        this.programKeypair.publicKey
+      )
+    )[0]
+    this.rateLimitPubkey = (
+      await PublicKey.findProgramAddress(
+        [
+          Buffer.from(App.rateLimitSeed, "utf-8"),
+          DEFAULT_NAMESPACE,
+          this.userKeypair.publicKey.toBuffer(),
+        ],
+        this.programKeypair.publicKey
+      )
+    )[0]
     const res = await this.connection.getAccountInfo(this.programKeypair.publicKey)
     if (!res) {
       console.error("Counter is not deployed. Deploy it first.")
@@ -68,8 +123,20 @@ export class App {
     console.log("counter", this.settingsPubkey.toBase58())
   }
 
-  async updateCounterSettings(admin: Uint8Array, inc_step: number, dec_step: number) {
-    const updateSettingsIx = new TransactionInstruction({
+  async initializeCounterSettings(
+    inc_step: number,
+    dec_step: number,
+    saturating: boolean,
+    min_value: BN,
+    max_value: BN
+  ) {
+    const programDataPubkey = (
+      await PublicKey.findProgramAddress(
+        [this.programKeypair.publicKey.toBuffer()],
+        App.bpfLoaderUpgradeableProgramId
+      )
+    )[0]
+    const initializeSettingsIx = new TransactionInstruction({
       programId: this.programKeypair.publicKey,
       keys: [
         {
@@ -80,8 +147,44 @@ export class App {
         { pubkey: this.settingsPubkey, isSigner: false, isWritable: true },
         { pubkey: SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false },
         { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+        { pubkey: programDataPubkey, isSigner: false, isWritable: false },
+      ],
+      data: encodeInitializeSettingsIx(
+        DEFAULT_NAMESPACE,
+        inc_step,
+        dec_step,
+        saturating,
+        min_value,
+        max_value
+      ),
+    })
+
+    const tx = new Transaction().add(initializeSettingsIx)
+    const txHash = await this.connection.sendTransaction(tx, [this.adminKeypair], {
+      preflightCommitment: "max",
+    })
+    console.log("initialize settings tx", txHash)
+    await delay(3000)
+  }
+
+  async updateCounterSettings(
+    inc_step: number,
+    dec_step: number,
+    saturating: boolean,
+    min_value: BN,
+    max_value: BN
+  ) {
+    const updateSettingsIx = new TransactionInstruction({
+      programId: this.programKeypair.publicKey,
+      keys: [
+        { pubkey: this.settingsPubkey, isSigner: false, isWritable: true },
+        {
+          pubkey: this.adminKeypair.publicKey,
+          isSigner: true,
+          isWritable: false,
+        },
       ],
-      data: encodeUpdateSettingsIx(admin, inc_step, dec_step),
+      data: encodeUpdateSettingsIx(inc_step, dec_step, saturating, min_value, max_value),
     })
 
     const tx = new Transaction().add(updateSettingsIx)
@@ -103,6 +206,14 @@ export class App {
     return decodeCounter(account.data)
   }
 
+  async isCounterInitialized(): Promise<boolean> {
+    return (await this.connection.getAccountInfo(this.counterPubkey)) !== null
+  }
+
+  async isSettingsInitialized(): Promise<boolean> {
+    return (await this.connection.getAccountInfo(this.settingsPubkey)) !== null
+  }
+
   async readSettingsAccount(): Promise<Settings> {
     const account = await this.connection.getAccountInfo(this.settingsPubkey)
     if (!account) {
@@ -113,16 +224,20 @@ export class App {
   }
 
   async createCounterAndInc() {
-    const data = encodeCounter(0, new BN(0))
-    const lamports = await this.connection.getMinimumBalanceForRentExemption(data.length)
-    const createAccountIx = SystemProgram.createAccountWithSeed({
-      fromPubkey: this.userKeypair.publicKey,
-      basePubkey: this.userKeypair.publicKey,
-      seed: App.counterSeed,
-      newAccountPubkey: this.counterPubkey,
-      space: data.length,
-      lamports: lamports,
+    const createCounterIx = new TransactionInstruction({
       programId: this.programKeypair.publicKey,
+      keys: [
+        {
+          pubkey: this.userKeypair.publicKey,
+          isSigner: true,
+          isWritable: true,
+        },
+        { pubkey: this.counterPubkey, isSigner: false, isWritable: true },
+        { pubkey: SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false },
+        { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+        { pubkey: this.statsPubkey, isSigner: false, isWritable: true },
+      ],
+      data: encodeCreateCounterIx(DEFAULT_NAMESPACE, App.counterSeed),
     })
 
     const incIx = new TransactionInstruction({
@@ -131,20 +246,53 @@ export class App {
         {
           pubkey: this.userKeypair.publicKey,
           isSigner: true,
-          isWritable: false,
+          isWritable: true,
         },
         { pubkey: this.counterPubkey, isSigner: false, isWritable: true },
         { pubkey: this.settingsPubkey, isSigner: false, isWritable: false },
+        { pubkey: this.treasuryPubkey, isSigner: false, isWritable: true },
+        { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+        { pubkey: this.historyPubkey, isSigner: false, isWritable: true },
+        { pubkey: this.statsPubkey, isSigner: false, isWritable: true },
+        { pubkey: this.leaderboardPubkey, isSigner: false, isWritable: true },
+        { pubkey: this.rateLimitPubkey, isSigner: false, isWritable: true },
       ],
       data: encodeIncIx(),
     })
 
-    const tx = new Transaction().add(createAccountIx, incIx)
+    const tx = new Transaction().add(createCounterIx, incIx)
     const res = await this.connection.sendTransaction(tx, [this.userKeypair])
     console.log("create counter and inc tx", res)
     await delay(3000)
   }
 
+  async incCounter() {
+    const incIx = new TransactionInstruction({
+      programId: this.programKeypair.publicKey,
+      keys: [
+        {
+          pubkey: this.userKeypair.publicKey,
+          isSigner: true,
+          isWritable: true,
+        },
+        { pubkey: this.counterPubkey, isSigner: false, isWritable: true },
+        { pubkey: this.settingsPubkey, isSigner: false, isWritable: false },
+        { pubkey: this.treasuryPubkey, isSigner: false, isWritable: true },
+        { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+        { pubkey: this.historyPubkey, isSigner: false, isWritable: true },
+        { pubkey: this.statsPubkey, isSigner: false, isWritable: true },
+        { pubkey: this.leaderboardPubkey, isSigner: false, isWritable: true },
+        { pubkey: this.rateLimitPubkey, isSigner: false, isWritable: true },
+      ],
+      data: encodeIncIx(),
+    })
+
+    const tx = new Transaction().add(incIx)
+    const res = await this.connection.sendTransaction(tx, [this.userKeypair])
+    console.log("inc counter tx", res)
+    await delay(3000)
+  }
+
   async decCounter() {
     const decIx = new TransactionInstruction({
       programId: this.programKeypair.publicKey,
@@ -152,10 +300,16 @@ export class App {
         {
           pubkey: this.userKeypair.publicKey,
           isSigner: true,
-          isWritable: false,
+          isWritable: true,
         },
         { pubkey: this.counterPubkey, isSigner: false, isWritable: true },
         { pubkey: this.settingsPubkey, isSigner: false, isWritable: false },
+        { pubkey: this.treasuryPubkey, isSigner: false, isWritable: true },
+        { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+        { pubkey: this.historyPubkey, isSigner: false, isWritable: true },
+        { pubkey: this.statsPubkey, isSigner: false, isWritable: true },
+        { pubkey: this.leaderboardPubkey, isSigner: false, isWritable: true },
+        { pubkey: this.rateLimitPubkey, isSigner: false, isWritable: true },
       ],
       data: encodeDecIx(),
     })
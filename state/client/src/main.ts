@@ -1,11 +1,22 @@
+import BN from "bn.js"
 import { App } from "./app"
 
 async function main() {
   const app = new App()
   await app.init()
 
-  await app.updateCounterSettings(app.adminKeypair.publicKey.toBytes(), 19, 98)
-  await app.createCounterAndInc()
+  const minValue = new BN("-9223372036854775808")
+  const maxValue = new BN("9223372036854775807")
+  if (await app.isSettingsInitialized()) {
+    await app.updateCounterSettings(19, 98, false, minValue, maxValue)
+  } else {
+    await app.initializeCounterSettings(19, 98, false, minValue, maxValue)
+  }
+  if (await app.isCounterInitialized()) {
+    await app.incCounter()
+  } else {
+    await app.createCounterAndInc()
+  }
   await app.decCounter()
 
   console.log("counter", await app.readCounterAccount())
@@ -1,36 +1,255 @@
 import lo from "buffer-layout"
 import BN from "bn.js"
 
+export interface AccountHeader {
+  discriminator: number[]
+  version: number
+}
+
 export interface Counter {
+  header: AccountHeader
   counter: number
   value: BN
+  namespace: number[]
+  label: number[]
+  bump: number
+  operator: number[]
+  allowance: BN
+  last_update_slot: BN
+  history: number[]
+  owner: number[]
+  in_stats: number
+  ranked: number
+}
+
+export interface History {
+  header: AccountHeader
+  counter: number[]
+  capacity: number
+  total: BN
+}
+
+export interface Stats {
+  header: AccountHeader
+  total_ops: BN
+  counters: BN
+  value_sum: BN
+  min_value: BN
+  max_value: BN
+}
+
+export interface Leaderboard {
+  header: AccountHeader
+  namespace: number[]
+  count: number
+  entries: LeaderboardEntry[]
+}
+
+export interface LeaderboardEntry {
+  counter: number[]
+  owner: number[]
+  value: BN
+}
+
+export interface HistoryEntry {
+  delta: BN
+  slot: BN
+  signer: number[]
 }
 
 export interface Settings {
-  admin: number[]
+  header: AccountHeader
+  admins: number[][]
+  admin_count: number
+  threshold: number
   inc_step: number
   dec_step: number
+  saturating: number
+  min_value: BN
+  max_value: BN
+  pending_admin: number[]
+  delay: number
+  pending_change: SettingsChange
+  pending_eta: BN
+  paused: number
+  guardian: number[]
+  namespace: number[]
+  max_delta: BN
+  min_interval: BN
+  fee_lamports: BN
+  treasury_bump: number
+  leaderboard_size: number
+  leaderboard_bump: number
+}
+
+export interface SettingsChange {
+  inc_step: number
+  dec_step: number
+  saturating: number
+  min_value: BN
+  max_value: BN
+  delay: number
+  fee_lamports: BN
+  max_delta: BN
+  min_interval: BN
 }
 
 enum CounterIxOrder {
   Inc = 0,
   Dec = 1,
   UpdateSettings = 2,
+  CreateCounter = 3,
+  CloseCounter = 4,
+  Migrate = 5,
+  ProposeAdmin = 6,
+  AcceptAdmin = 7,
+  CancelAdminProposal = 8,
+  InitializeSettings = 9,
+  RemoveAdmin = 10,
+  SetThreshold = 11,
+  QueueSettingsChange = 12,
+  ExecuteSettingsChange = 13,
+  CancelSettingsChange = 14,
+  Pause = 15,
+  Unpause = 16,
+  SetGuardian = 17,
+  Add = 18,
+  Sub = 19,
+  Set = 20,
+  SetMaxDelta = 21,
+  Batch = 22,
+  ApproveOperator = 23,
+  RevokeOperator = 24,
+  SetMinInterval = 25,
+  SetFee = 26,
+  WithdrawTreasury = 27,
+  GetCounter = 28,
+  CreateHistory = 29,
+  InitializeStats = 30,
+  ResetStats = 31,
+  SetLeaderboardSize = 32,
+  ReclaimSettings = 33,
+}
+
+export type CounterOp =
+  | { kind: "inc" }
+  | { kind: "dec" }
+  | { kind: "add"; amount: BN }
+  | { kind: "sub"; amount: BN }
+
+const MAX_ADMINS = 8
+const MAX_LEADERBOARD_SIZE = 32
+
+// Namespace of the settings account created before namespaces were introduced
+export const DEFAULT_NAMESPACE = new Uint8Array(32)
+
+// 64-bit integers as BN, the nu64 and ns64 layouts of buffer-layout lose precision above 2^53
+class Int64Layout extends lo.Layout {
+  signed: boolean
+
+  constructor(signed: boolean, property?: string) {
+    super(8, property)
+    this.signed = signed
+  }
+
+  decode(b: Buffer, offset = 0): BN {
+    const value = new BN(b.subarray(offset, offset + 8), "le")
+    return this.signed ? value.fromTwos(64) : value
+  }
+
+  encode(src: BN, b: Buffer, offset = 0): number {
+    const value = this.signed ? src.toTwos(64) : src
+    value.toArrayLike(Buffer, "le", 8).copy(b, offset)
+    return 8
+  }
 }
 
-const counterSchema = lo.struct([lo.u32("counter"), lo.ns64("value")])
+const u64 = (property?: string) => new Int64Layout(false, property)
+const i64 = (property?: string) => new Int64Layout(true, property)
+
+const headerSchema = lo.struct([lo.seq(lo.u8(), 8, "discriminator"), lo.u8("version")], "header")
+const counterSchema = lo.struct([
+  headerSchema,
+  lo.u32("counter"),
+  i64("value"),
+  lo.seq(lo.u8(), 32, "namespace"),
+  lo.seq(lo.u8(), 32, "label"),
+  lo.u8("bump"),
+  lo.seq(lo.u8(), 32, "operator"),
+  u64("allowance"),
+  u64("last_update_slot"),
+  lo.seq(lo.u8(), 32, "history"),
+  lo.seq(lo.u8(), 32, "owner"),
+  lo.u8("in_stats"),
+  lo.u8("ranked"),
+])
+const historySchema = lo.struct([
+  headerSchema,
+  lo.seq(lo.u8(), 32, "counter"),
+  lo.u32("capacity"),
+  u64("total"),
+])
+const statsSchema = lo.struct([
+  headerSchema,
+  u64("total_ops"),
+  u64("counters"),
+  lo.blob(16, "value_sum"),
+  i64("min_value"),
+  i64("max_value"),
+])
+const leaderboardSchema = lo.struct([
+  headerSchema,
+  lo.seq(lo.u8(), 32, "namespace"),
+  lo.u8("count"),
+  lo.seq(
+    lo.struct([lo.seq(lo.u8(), 32, "counter"), lo.seq(lo.u8(), 32, "owner"), i64("value")]),
+    MAX_LEADERBOARD_SIZE,
+    "entries"
+  ),
+])
+const historyEntrySchema = lo.struct([
+  i64("delta"),
+  u64("slot"),
+  lo.seq(lo.u8(), 32, "signer"),
+])
 const settingsSchema = lo.struct([
-  lo.seq(lo.u8(), 32, "admin"),
+  headerSchema,
+  lo.seq(lo.seq(lo.u8(), 32), MAX_ADMINS, "admins"),
+  lo.u8("admin_count"),
+  lo.u8("threshold"),
   lo.u32("inc_step"),
   lo.u32("dec_step"),
+  lo.u8("saturating"),
+  i64("min_value"),
+  i64("max_value"),
+  lo.seq(lo.u8(), 32, "pending_admin"),
+  lo.u32("delay"),
+  lo.struct(
+    [
+      lo.u32("inc_step"),
+      lo.u32("dec_step"),
+      lo.u8("saturating"),
+      i64("min_value"),
+      i64("max_value"),
+      lo.u32("delay"),
+      u64("fee_lamports"),
+      u64("max_delta"),
+      u64("min_interval"),
+    ],
+    "pending_change"
+  ),
+  i64("pending_eta"),
+  lo.u8("paused"),
+  lo.seq(lo.u8(), 32, "guardian"),
+  lo.seq(lo.u8(), 32, "namespace"),
+  u64("max_delta"),
+  u64("min_interval"),
+  u64("fee_lamports"),
+  lo.u8("treasury_bump"),
+  lo.u8("leaderboard_size"),
+  lo.u8("leaderboard_bump"),
 ])
 
-export function encodeCounter(counter: number, value: BN): Buffer {
-  const b = Buffer.alloc(4 + 8)
-  counterSchema.encode({ counter, value }, b)
-  return b
-}
-
 export function decodeCounter(data: Buffer): Counter {
   return counterSchema.decode(data)
 }
@@ -39,6 +258,31 @@ export function decodeSettings(data: Buffer): Settings {
   return settingsSchema.decode(data)
 }
 
+// Only the entries in use, from the highest value
+export function decodeLeaderboard(data: Buffer): Leaderboard {
+  const leaderboard: Leaderboard = leaderboardSchema.decode(data)
+  return { ...leaderboard, entries: leaderboard.entries.slice(0, leaderboard.count) }
+}
+
+export function decodeStats(data: Buffer): Stats {
+  const stats = statsSchema.decode(data)
+  // i128 isn't supported by buffer-layout
+  return { ...stats, value_sum: new BN(stats.value_sum, "le").fromTwos(128) }
+}
+
+// Entries of a history from the oldest to the newest
+export function decodeHistory(data: Buffer): { history: History; entries: HistoryEntry[] } {
+  const history: History = historySchema.decode(data)
+  const total = history.total
+  const count = BN.min(total, new BN(history.capacity)).toNumber()
+  const entries = []
+  for (let i = total.subn(count); i.lt(total); i = i.addn(1)) {
+    const offset = historySchema.span + i.modn(history.capacity) * historyEntrySchema.span
+    entries.push(historyEntrySchema.decode(data, offset))
+  }
+  return { history, entries }
+}
+
 export function encodeIncIx(): Buffer {
   return Buffer.from([CounterIxOrder.Inc])
 }
@@ -47,13 +291,215 @@ export function encodeDecIx(): Buffer {
   return Buffer.from([CounterIxOrder.Dec])
 }
 
+export function encodeCreateCounterIx(namespace: Uint8Array, label: string): Buffer {
+  const l = Buffer.from(label, "utf-8")
+  const len = Buffer.alloc(4)
+  len.writeUInt32LE(l.length)
+  return Buffer.from([CounterIxOrder.CreateCounter, ...namespace, ...len, ...l])
+}
+
+export function encodeCloseCounterIx(): Buffer {
+  return Buffer.from([CounterIxOrder.CloseCounter])
+}
+
+export function encodeMigrateIx(): Buffer {
+  return Buffer.from([CounterIxOrder.Migrate])
+}
+
+function encodeSettingsArgs(
+  inc_step: number,
+  dec_step: number,
+  saturating: boolean,
+  min_value: BN,
+  max_value: BN
+): Buffer {
+  const schema = lo.struct([
+    lo.u32("inc_step"),
+    lo.u32("dec_step"),
+    lo.u8("saturating"),
+    i64("min_value"),
+    i64("max_value"),
+  ])
+  const b = Buffer.alloc(4 + 4 + 1 + 8 + 8)
+  schema.encode({ inc_step, dec_step, saturating: saturating ? 1 : 0, min_value, max_value }, b)
+  return b
+}
+
 export function encodeUpdateSettingsIx(
-  admin: Uint8Array,
   inc_step: number,
-  dec_step: number
+  dec_step: number,
+  saturating: boolean,
+  min_value: BN,
+  max_value: BN
 ): Buffer {
-  const schema = lo.struct([lo.seq(lo.u8(), 32, "admin"), lo.u32("inc_step"), lo.u32("dec_step")])
-  const b = Buffer.alloc(32 + 4 + 4)
-  schema.encode({ admin, inc_step, dec_step }, b)
+  const b = encodeSettingsArgs(inc_step, dec_step, saturating, min_value, max_value)
   return Buffer.from([CounterIxOrder.UpdateSettings, ...b])
 }
+
+export function encodeInitializeSettingsIx(
+  namespace: Uint8Array,
+  inc_step: number,
+  dec_step: number,
+  saturating: boolean,
+  min_value: BN,
+  max_value: BN
+): Buffer {
+  const b = encodeSettingsArgs(inc_step, dec_step, saturating, min_value, max_value)
+  return Buffer.from([CounterIxOrder.InitializeSettings, ...namespace, ...b])
+}
+
+export function encodeProposeAdminIx(newAdmin: Uint8Array): Buffer {
+  return Buffer.from([CounterIxOrder.ProposeAdmin, ...newAdmin])
+}
+
+export function encodeAcceptAdminIx(): Buffer {
+  return Buffer.from([CounterIxOrder.AcceptAdmin])
+}
+
+export function encodeReclaimSettingsIx(): Buffer {
+  return Buffer.from([CounterIxOrder.ReclaimSettings])
+}
+
+export function encodeCancelAdminProposalIx(): Buffer {
+  return Buffer.from([CounterIxOrder.CancelAdminProposal])
+}
+
+export function encodeRemoveAdminIx(admin: Uint8Array): Buffer {
+  return Buffer.from([CounterIxOrder.RemoveAdmin, ...admin])
+}
+
+export function encodeSetThresholdIx(threshold: number): Buffer {
+  return Buffer.from([CounterIxOrder.SetThreshold, threshold])
+}
+
+export function encodeQueueSettingsChangeIx(
+  inc_step: number,
+  dec_step: number,
+  saturating: boolean,
+  min_value: BN,
+  max_value: BN,
+  delay: number,
+  fee_lamports: BN,
+  max_delta: BN,
+  min_interval: BN
+): Buffer {
+  const b = encodeSettingsArgs(inc_step, dec_step, saturating, min_value, max_value)
+  const d = Buffer.alloc(4 + 8 + 8 + 8)
+  d.writeUInt32LE(delay)
+  u64().encode(fee_lamports, d, 4)
+  u64().encode(max_delta, d, 12)
+  u64().encode(min_interval, d, 20)
+  return Buffer.from([CounterIxOrder.QueueSettingsChange, ...b, ...d])
+}
+
+export function encodeExecuteSettingsChangeIx(): Buffer {
+  return Buffer.from([CounterIxOrder.ExecuteSettingsChange])
+}
+
+export function encodeCancelSettingsChangeIx(): Buffer {
+  return Buffer.from([CounterIxOrder.CancelSettingsChange])
+}
+
+export function encodePauseIx(): Buffer {
+  return Buffer.from([CounterIxOrder.Pause])
+}
+
+export function encodeUnpauseIx(): Buffer {
+  return Buffer.from([CounterIxOrder.Unpause])
+}
+
+export function encodeSetGuardianIx(guardian: Uint8Array): Buffer {
+  return Buffer.from([CounterIxOrder.SetGuardian, ...guardian])
+}
+
+function encodeI64(value: BN): Buffer {
+  const b = Buffer.alloc(8)
+  i64().encode(value, b)
+  return b
+}
+
+export function encodeAddIx(amount: BN): Buffer {
+  return Buffer.from([CounterIxOrder.Add, ...encodeI64(amount)])
+}
+
+export function encodeSubIx(amount: BN): Buffer {
+  return Buffer.from([CounterIxOrder.Sub, ...encodeI64(amount)])
+}
+
+export function encodeSetIx(value: BN): Buffer {
+  return Buffer.from([CounterIxOrder.Set, ...encodeI64(value)])
+}
+
+export function encodeSetMaxDeltaIx(max_delta: BN): Buffer {
+  const b = Buffer.alloc(8)
+  u64().encode(max_delta, b)
+  return Buffer.from([CounterIxOrder.SetMaxDelta, ...b])
+}
+
+export function encodeBatchIx(ops: CounterOp[]): Buffer {
+  const len = Buffer.alloc(4)
+  len.writeUInt32LE(ops.length)
+  const encoded = ops.map((op) => {
+    switch (op.kind) {
+      case "inc":
+        return Buffer.from([0])
+      case "dec":
+        return Buffer.from([1])
+      case "add":
+        return Buffer.from([2, ...encodeI64(op.amount)])
+      case "sub":
+        return Buffer.from([3, ...encodeI64(op.amount)])
+    }
+  })
+  return Buffer.concat([Buffer.from([CounterIxOrder.Batch]), len, ...encoded])
+}
+
+export function encodeApproveOperatorIx(operator: Uint8Array, allowance: BN): Buffer {
+  const b = Buffer.alloc(8)
+  u64().encode(allowance, b)
+  return Buffer.from([CounterIxOrder.ApproveOperator, ...operator, ...b])
+}
+
+export function encodeRevokeOperatorIx(): Buffer {
+  return Buffer.from([CounterIxOrder.RevokeOperator])
+}
+
+export function encodeSetMinIntervalIx(min_interval: BN): Buffer {
+  const b = Buffer.alloc(8)
+  u64().encode(min_interval, b)
+  return Buffer.from([CounterIxOrder.SetMinInterval, ...b])
+}
+
+export function encodeSetFeeIx(fee_lamports: BN): Buffer {
+  const b = Buffer.alloc(8)
+  u64().encode(fee_lamports, b)
+  return Buffer.from([CounterIxOrder.SetFee, ...b])
+}
+
+export function encodeWithdrawTreasuryIx(amount: BN): Buffer {
+  const b = Buffer.alloc(8)
+  u64().encode(amount, b)
+  return Buffer.from([CounterIxOrder.WithdrawTreasury, ...b])
+}
+
+export function encodeGetCounterIx(): Buffer {
+  return Buffer.from([CounterIxOrder.GetCounter])
+}
+
+export function encodeCreateHistoryIx(capacity: number): Buffer {
+  const b = Buffer.alloc(4)
+  b.writeUInt32LE(capacity)
+  return Buffer.from([CounterIxOrder.CreateHistory, ...b])
+}
+
+export function encodeInitializeStatsIx(): Buffer {
+  return Buffer.from([CounterIxOrder.InitializeStats])
+}
+
+export function encodeResetStatsIx(): Buffer {
+  return Buffer.from([CounterIxOrder.ResetStats])
+}
+
+export function encodeSetLeaderboardSizeIx(size: number): Buffer {
+  return Buffer.from([CounterIxOrder.SetLeaderboardSize, size])
+}
@@ -0,0 +1,152 @@
+use solana_program::{
+    account_info::AccountInfo,
+    instruction::{AccountMeta, Instruction},
+    program::{get_return_data, invoke, invoke_signed},
+    program_error::ProgramError,
+};
+
+use crate::id;
+use crate::instruction::{CounterInstruction, CounterOp};
+use crate::state::{Counter, Versioned};
+
+/// Accounts of the instructions changing a counter.
+#[derive(Clone)]
+pub struct CounterAccounts<'a> {
+    /// Owner or operator of the counter, signs and pays the fee
+    pub user: AccountInfo<'a>,
+    pub counter: AccountInfo<'a>,
+    pub settings: AccountInfo<'a>,
+    /// Needed only if the namespace charges a fee
+    pub fee: Option<FeeAccounts<'a>>,
+    /// Needed only if the counter has a history
+    pub history: Option<AccountInfo<'a>>,
+    /// Needed only if the counter is counted by the stats, see `Counter::in_stats`
+    pub stats: Option<AccountInfo<'a>>,
+    /// Needed only if the namespace has a leaderboard
+    pub leaderboard: Option<AccountInfo<'a>>,
+    /// Needed only if the namespace has a min interval
+    pub rate_limit: Option<RateLimitAccounts<'a>>,
+}
+
+#[derive(Clone)]
+pub struct FeeAccounts<'a> {
+    pub treasury: AccountInfo<'a>,
+    pub system_program: AccountInfo<'a>,
+}
+
+#[derive(Clone)]
+pub struct RateLimitAccounts<'a> {
+    /// Rate limit account of the owner of the counter
+    pub rate_limit: AccountInfo<'a>,
+    /// Creates the rate limit account on the first change of the owner
+    pub system_program: AccountInfo<'a>,
+}
+
+pub type IncAccounts<'a> = CounterAccounts<'a>;
+pub type DecAccounts<'a> = CounterAccounts<'a>;
+
+impl<'a> CounterAccounts<'a> {
+    fn instruction(&self, data: &CounterInstruction) -> Instruction {
+        let mut accounts = vec![
+            AccountMeta::new(*self.user.key, true),
+            AccountMeta::new(*self.counter.key, false),
+            AccountMeta::new_readonly(*self.settings.key, false),
+        ];
+        if let Some(fee) = &self.fee {
+            accounts.push(AccountMeta::new(*fee.treasury.key, false));
+            accounts.push(AccountMeta::new_readonly(*fee.system_program.key, false));
+        }
+        if let Some(history) = &self.history {
+            accounts.push(AccountMeta::new(*history.key, false));
+        }
+        if let Some(stats) = &self.stats {
+            accounts.push(AccountMeta::new(*stats.key, false));
+        }
+        if let Some(leaderboard) = &self.leaderboard {
+            accounts.push(AccountMeta::new(*leaderboard.key, false));
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            accounts.push(AccountMeta::new(*rate_limit.rate_limit.key, false));
+            accounts.push(AccountMeta::new_readonly(*rate_limit.system_program.key, false));
+        }
+        Instruction::new_with_borsh(id(), data, accounts)
+    }
+
+    fn account_infos(&self) -> Vec<AccountInfo<'a>> {
+        let mut infos = vec![self.user.clone(), self.counter.clone(), self.settings.clone()];
+        if let Some(fee) = &self.fee {
+            infos.push(fee.treasury.clone());
+            infos.push(fee.system_program.clone());
+        }
+        if let Some(history) = &self.history {
+            infos.push(history.clone());
+        }
+        if let Some(stats) = &self.stats {
+            infos.push(stats.clone());
+        }
+        if let Some(leaderboard) = &self.leaderboard {
+            infos.push(leaderboard.clone());
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            infos.push(rate_limit.rate_limit.clone());
+            infos.push(rate_limit.system_program.clone());
+        }
+        infos
+    }
+
+    /// Invoke a counter instruction, `signer_seeds` sign for a PDA user, and return the counter.
+    fn invoke(
+        &self,
+        data: &CounterInstruction,
+        signer_seeds: &[&[&[u8]]],
+    ) -> Result<Counter, ProgramError> {
+        invoke_signed(&self.instruction(data), &self.account_infos(), signer_seeds)?;
+        returned_counter()
+    }
+}
+
+pub fn inc(accounts: &IncAccounts, signer_seeds: &[&[&[u8]]]) -> Result<Counter, ProgramError> {
+    accounts.invoke(&CounterInstruction::Inc, signer_seeds)
+}
+
+pub fn dec(accounts: &DecAccounts, signer_seeds: &[&[&[u8]]]) -> Result<Counter, ProgramError> {
+    accounts.invoke(&CounterInstruction::Dec, signer_seeds)
+}
+
+pub fn add(
+    accounts: &CounterAccounts,
+    amount: i64,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<Counter, ProgramError> {
+    accounts.invoke(&CounterInstruction::Add { amount }, signer_seeds)
+}
+
+pub fn sub(
+    accounts: &CounterAccounts,
+    amount: i64,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<Counter, ProgramError> {
+    accounts.invoke(&CounterInstruction::Sub { amount }, signer_seeds)
+}
+
+pub fn batch(
+    accounts: &CounterAccounts,
+    ops: Vec<CounterOp>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<Counter, ProgramError> {
+    accounts.invoke(&CounterInstruction::Batch { ops }, signer_seeds)
+}
+
+/// Counter returned by the last instruction of this program invoked with CPI.
+pub fn returned_counter() -> Result<Counter, ProgramError> {
+    match get_return_data() {
+        Some((program_id, data)) if program_id == id() => Counter::unpack(&data),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+/// Read a counter through the program. Fails if it isn't an up to date counter account.
+pub fn get_counter(counter_info: &AccountInfo) -> Result<Counter, ProgramError> {
+    invoke(&CounterInstruction::get_counter(counter_info.key), std::slice::from_ref(counter_info))?;
+    returned_counter()
+}
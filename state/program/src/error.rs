@@ -11,6 +11,99 @@ pub enum CounterError {
 
     #[error("Wrong settings PDA")]
     WrongSettingsPDA,
+
+    #[error("Counter value overflow")]
+    ValueOverflow,
+
+    #[error("Counter operations count overflow")]
+    OpCountOverflow,
+
+    #[error("Counter value is out of bounds")]
+    OutOfBounds,
+
+    #[error("Account layout is outdated, migrate it first")]
+    OutdatedAccountVersion,
+
+    #[error("Account has a wrong type")]
+    InvalidAccountType,
+
+    #[error("Signature of the proposed admin is required")]
+    PendingAdminRequired,
+
+    #[error("Upgrade authority signature is required")]
+    UpgradeAuthorityRequired,
+
+    #[error("Admin set is full")]
+    TooManyAdmins,
+
+    #[error("Account is not in the admin set")]
+    UnknownAdmin,
+
+    #[error("Threshold must be between 1 and the number of admins")]
+    InvalidThreshold,
+
+    #[error("Settings are timelocked, queue the change instead")]
+    TimelockRequired,
+
+    #[error("There is no queued settings change")]
+    NoPendingSettingsChange,
+
+    #[error("Queued settings change can't be executed yet")]
+    TimelockNotExpired,
+
+    #[error("Program is paused")]
+    ProgramPaused,
+
+    #[error("Counter label must be 1 to 32 bytes without zero bytes")]
+    InvalidLabel,
+
+    #[error("Amount must be positive")]
+    InvalidAmount,
+
+    #[error("Amount is above the max_delta of the settings")]
+    DeltaTooLarge,
+
+    #[error("Batch has too many operations")]
+    TooManyOps,
+
+    #[error("Operator allowance is exhausted")]
+    AllowanceExceeded,
+
+    #[error("Counter was changed too recently")]
+    RateLimited,
+
+    #[error("Wrong treasury PDA of the settings")]
+    WrongTreasuryPDA,
+
+    #[error("Wrong history PDA of the counter")]
+    WrongHistoryPDA,
+
+    #[error("History capacity must be 1 to 200 entries")]
+    InvalidHistoryCapacity,
+
+    #[error("The counter has a history, its account is required")]
+    HistoryRequired,
+
+    #[error("Wrong stats PDA")]
+    WrongStatsPDA,
+
+    #[error("The counter is counted by the stats, their account is required")]
+    StatsRequired,
+
+    #[error("Wrong leaderboard PDA of the settings")]
+    WrongLeaderboardPDA,
+
+    #[error("Leaderboard size must be at most 32")]
+    InvalidLeaderboardSize,
+
+    #[error("The namespace has a leaderboard, its account is required")]
+    LeaderboardRequired,
+
+    #[error("Settings have a usable admin")]
+    SettingsNotReclaimable,
+
+    #[error("The settings have a rate limit, the rate limit account of the owner is required")]
+    RateLimitRequired,
 }
 
 impl From<CounterError> for ProgramError {
@@ -0,0 +1,174 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::log::sol_log_data;
+
+use crate::id;
+use crate::state::SettingsChange;
+
+const PROGRAM_DATA: &str = "Program data: ";
+
+/// Events logged with `sol_log_data`, one borsh serialized event per log line.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum CounterEvent {
+    /// `Inc` or `Add` changed a counter
+    Incremented { counter: [u8; 32], amount: u64, value: i64 },
+
+    /// `Dec` or `Sub` changed a counter
+    Decremented { counter: [u8; 32], amount: u64, value: i64 },
+
+    /// Settings values were changed directly or by an executed timelocked change
+    SettingsUpdated { settings: [u8; 32], old: SettingsChange, new: SettingsChange },
+
+    /// Admins overwrote the value of a counter with `Set`
+    Set { counter: [u8; 32], old: i64, value: i64 },
+}
+
+impl CounterEvent {
+    pub fn emit(&self) {
+        sol_log_data(&[&self.try_to_vec().unwrap()]);
+    }
+
+    /// Decode the events of this program from the log messages of a transaction.
+    /// Data logged by other programs, including the ones this program invokes, is skipped.
+    /// If any top-level instruction failed the whole transaction was rolled back, and no events
+    /// are returned.
+    pub fn from_logs<S: AsRef<str>>(logs: &[S]) -> Vec<CounterEvent> {
+        let program = format!("Program {} ", id());
+        // invocation stack, whether each program is this one and the events of its frame
+        let mut stack: Vec<(bool, Vec<CounterEvent>)> = Vec::new();
+        let mut events = Vec::new();
+        for line in logs.iter().map(AsRef::as_ref) {
+            if let Some(data) = line.strip_prefix(PROGRAM_DATA) {
+                if let Some((true, frame_events)) = stack.last_mut() {
+                    frame_events.extend(data.split(' ').filter_map(Self::decode));
+                }
+            } else if line.starts_with("Program log: ") {
+                continue;
+            } else if line.starts_with("Program ") && line.contains(" invoke [") {
+                stack.push((line.starts_with(&program), Vec::new()));
+            } else if line.starts_with("Program ") && line.ends_with(" success") {
+                // a successful frame hands its events to the caller
+                if let Some((_, frame_events)) = stack.pop() {
+                    match stack.last_mut() {
+                        Some((_, caller_events)) => caller_events.extend(frame_events),
+                        None => events.extend(frame_events),
+                    }
+                }
+            } else if line.starts_with("Program ") && line.contains(" failed: ") {
+                // an instruction fails along with the frames it invoked, reverting the transaction
+                if stack.len() == 1 {
+                    return Vec::new();
+                }
+                stack.pop();
+            }
+        }
+        events
+    }
+
+    fn decode(data: &str) -> Option<CounterEvent> {
+        let bytes = base64::decode(data).ok()?;
+        CounterEvent::try_from_slice(&bytes).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use borsh::BorshSerialize;
+
+    use crate::event::CounterEvent;
+    use crate::id;
+    use crate::state::SettingsChange;
+
+    fn data_line(event: &CounterEvent) -> String {
+        format!("Program data: {}", base64::encode(event.try_to_vec().unwrap()))
+    }
+
+    #[test]
+    fn test_serialization() {
+        let data = CounterEvent::Incremented { counter: [1; 32], amount: 2, value: -3 }
+            .try_to_vec()
+            .unwrap();
+        assert_eq!(
+            data,
+            [
+                &[0][..],
+                &[1; 32],
+                &[2, 0, 0, 0, 0, 0, 0, 0],
+                &[253, 255, 255, 255, 255, 255, 255, 255],
+            ]
+            .concat()
+        );
+
+        let data = CounterEvent::Set { counter: [1; 32], old: -3, value: 2 }.try_to_vec().unwrap();
+        assert_eq!(
+            data,
+            [
+                &[3][..],
+                &[1; 32],
+                &[253, 255, 255, 255, 255, 255, 255, 255],
+                &[2, 0, 0, 0, 0, 0, 0, 0],
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_from_logs() {
+        let inc = CounterEvent::Incremented { counter: [1; 32], amount: 1, value: 1 };
+        let dec = CounterEvent::Decremented { counter: [1; 32], amount: 2, value: -1 };
+        let settings = CounterEvent::SettingsUpdated {
+            settings: [2; 32],
+            old: SettingsChange::default(),
+            new: SettingsChange { inc_step: 3, ..SettingsChange::default() },
+        };
+        let other = "11111111111111111111111111111111";
+        let logs = vec![
+            format!("Program {} invoke [1]", id()),
+            "Program log: process_inc".to_string(),
+            data_line(&inc),
+            format!("Program {} invoke [2]", other),
+            data_line(&dec),
+            format!("Program {} success", other),
+            data_line(&dec),
+            "Program data: bm90IGFuIGV2ZW50".to_string(),
+            format!("Program {} consumed 5000 of 200000 compute units", id()),
+            format!("Program {} success", id()),
+            format!("Program {} invoke [1]", other),
+            data_line(&inc),
+            format!("Program {} success", other),
+            format!("Program {} invoke [1]", id()),
+            data_line(&settings),
+            format!("Program {} success", id()),
+        ];
+        assert_eq!(CounterEvent::from_logs(&logs), [inc, dec, settings]);
+    }
+
+    #[test]
+    fn test_from_logs_failed_transaction() {
+        let inc = CounterEvent::Incremented { counter: [1; 32], amount: 1, value: 1 };
+        let dec = CounterEvent::Decremented { counter: [1; 32], amount: 2, value: -1 };
+        let other = "11111111111111111111111111111111";
+
+        // the second instruction fails, which reverts the first one too
+        let logs = vec![
+            format!("Program {} invoke [1]", id()),
+            data_line(&inc),
+            format!("Program {} success", id()),
+            format!("Program {} invoke [1]", other),
+            format!("Program {} invoke [2]", id()),
+            data_line(&dec),
+            format!("Program {} success", id()),
+            format!("Program {} failed: custom program error: 0x1", other),
+        ];
+        assert!(CounterEvent::from_logs(&logs).is_empty());
+
+        // a failed invocation fails the instruction that invoked it
+        let logs = vec![
+            format!("Program {} invoke [1]", other),
+            format!("Program {} invoke [2]", id()),
+            data_line(&inc),
+            format!("Program {} failed: custom program error: 0x0", id()),
+            format!("Program {} failed: custom program error: 0x0", other),
+        ];
+        assert!(CounterEvent::from_logs(&logs).is_empty());
+    }
+}
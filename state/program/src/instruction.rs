@@ -6,82 +6,922 @@ use solana_program::{
 };
 
 use crate::{
-    id,
-    state::{Counter, Settings},
+    get_program_data_pubkey, id,
+    state::{Counter, History, Leaderboard, RateLimit, Settings, SettingsChange, Stats},
+    COUNTER_SEED, DEFAULT_NAMESPACE,
 };
 
+/// Largest number of operations in a `Batch`. A full batch with the fee, history, stats,
+/// leaderboard and rate limit enabled has to fit the default compute budget of an instruction,
+/// `test_batch_compute_budget` checks it when the BPF program runs with `cargo test-bpf`.
+pub const MAX_BATCH_OPS: usize = 64;
+
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
 pub enum CounterInstruction {
-    /// Increment a counter.
+    /// Increment a counter. Returns the updated counter.
     /// Accounts:
-    /// 0. `[signer]` owner of a counter
-    /// 1. `[writable]` counter_account, PDA
-    /// 2. `[]` settings_account, PDA
+    /// 0. `[signer, writable]` owner or operator of a counter, pays the fee and the rate limit
+    ///    account
+    /// 1. `[writable]` counter_account, PDA derived from the owner with the label
+    /// 2. `[]` settings_account of the counter namespace, PDA
+    /// 3. `[writable]` treasury_account of the namespace, only needed if there is a fee
+    /// 4. `[]` System program, only needed if there is a fee or the rate limit account is created
+    /// 5. `[writable]` history_account of the counter, only needed if it has one
+    /// 6. `[writable]` stats_account, PDA `[STATS_SEED]`, only optional for a counter
+    ///    created before the stats, whose changes are left out then
+    /// 7. `[writable]` leaderboard_account of the namespace, only needed if it has one
+    /// 8. `[writable]` rate_limit_account of the owner, PDA `[RATE_LIMIT_SEED, namespace, owner]`,
+    ///    only needed if there is a min interval. Created by the first change of the owner
     Inc,
 
-    /// Decrement a counter.
+    /// Decrement a counter. Returns the updated counter.
     /// Accounts:
-    /// 0. `[signer]` owner of a counter
-    /// 2. `[writable]` counter_account, PDA
-    /// 2. `[]` settings_account, PDA
+    /// 0. `[signer, writable]` owner or operator of a counter, pays the fee and the rate limit
+    ///    account
+    /// 1. `[writable]` counter_account, PDA derived from the owner with the label
+    /// 2. `[]` settings_account of the counter namespace, PDA
+    /// 3. `[writable]` treasury_account of the namespace, only needed if there is a fee
+    /// 4. `[]` System program, only needed if there is a fee or the rate limit account is created
+    /// 5. `[writable]` history_account of the counter, only needed if it has one
+    /// 6. `[writable]` stats_account, PDA `[STATS_SEED]`, only optional for a counter
+    ///    created before the stats, whose changes are left out then
+    /// 7. `[writable]` leaderboard_account of the namespace, only needed if it has one
+    /// 8. `[writable]` rate_limit_account of the owner, PDA `[RATE_LIMIT_SEED, namespace, owner]`,
+    ///    only needed if there is a min interval. Created by the first change of the owner
     Dec,
 
-    /// Update settings for counters. Only admin can do it.
+    /// Update settings for counters. Requires `threshold` signatures from the admin set.
+    /// Only possible while the timelock delay is zero, otherwise use `QueueSettingsChange`.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1.. `[signer]` Admins of counters
+    UpdateSettings {
+        inc_step: u32,
+        dec_step: u32,
+        saturating: bool,
+        min_value: i64,
+        max_value: i64,
+    },
+
+    /// Create a labeled counter account for a user, bound to the settings of a namespace.
+    /// The user pays for the rent.
+    /// Accounts:
+    /// 0. `[signer, writable]` owner of a counter
+    /// 1. `[writable]` counter_account, PDA derived from the owner with the label
+    /// 2. `[]` Rent sysvar
+    /// 3. `[]` System program
+    /// 4. `[writable]` stats_account, PDA `[STATS_SEED]`, whether or not it was created
+    CreateCounter { namespace: [u8; 32], label: String },
+
+    /// Close a counter account and send its lamports to a destination account.
+    /// The history of the counter is closed along with it, its leaderboard entry is removed.
     /// Accounts:
-    /// 0. `[signer, writable]` Admin of counters
+    /// 0. `[signer]` owner of a counter
+    /// 1. `[writable]` counter_account, PDA derived from the owner with the label
+    /// 2. `[writable]` destination account for the reclaimed lamports
+    /// 3. `[writable]` history_account of the counter, only needed if it has one
+    /// 4. `[writable]` stats_account, PDA `[STATS_SEED]`, only optional for a counter
+    ///    created before the stats, whose changes are left out then
+    /// 5. `[writable]` leaderboard_account, PDA `[LEADERBOARD_SEED, namespace]`, only needed if
+    ///    the counter was changed while the namespace had a leaderboard, see `Counter::ranked`
+    CloseCounter,
+
+    /// Upgrade a counter or settings account of an older layout to the current one.
+    /// The payer covers the rent for the grown account.
+    /// Accounts:
+    /// 0. `[signer, writable]` payer
+    /// 1. `[writable]` counter_account or settings_account
+    /// 2. `[]` Rent sysvar
+    /// 3. `[]` System program
+    Migrate,
+
+    /// Propose a new admin for the admin set.
+    /// The new admin has to accept the role with `AcceptAdmin`.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1.. `[signer]` Admins of counters
+    ProposeAdmin { new_admin: [u8; 32] },
+
+    /// Accept the admin role proposed with `ProposeAdmin` and join the admin set.
+    /// Accounts:
+    /// 0. `[signer]` Proposed admin
+    /// 1. `[writable]` settings_account, PDA
+    AcceptAdmin,
+
+    /// Cancel a pending admin proposal.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1.. `[signer]` Admins of counters
+    CancelAdminProposal,
+
+    /// Create the settings account of a namespace. The creator becomes the only admin
+    /// with a threshold of 1. Only the upgrade authority of the program can create
+    /// the settings of the default namespace.
+    /// Accounts:
+    /// 0. `[signer, writable]` Creator, the upgrade authority for the default namespace
     /// 1. `[writable]` settings_account, PDA
     /// 2. `[]` Rent sysvar
     /// 3. `[]` System program
-    UpdateSettings { admin: [u8; 32], inc_step: u32, dec_step: u32 },
+    /// 4. `[]` program_data_account of the upgradeable loader, PDA, only needed for the default
+    ///    namespace
+    InitializeSettings {
+        namespace: [u8; 32],
+        inc_step: u32,
+        dec_step: u32,
+        saturating: bool,
+        min_value: i64,
+        max_value: i64,
+    },
+
+    /// Remove an admin from the admin set. The threshold has to stay reachable.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1.. `[signer]` Admins of counters
+    RemoveAdmin { admin: [u8; 32] },
+
+    /// Change the number of admin signatures required to change settings.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1.. `[signer]` Admins of counters
+    SetThreshold { threshold: u8 },
+
+    /// Queue a change of all `SettingsChange` values, it can be executed after the timelock
+    /// delay. Replaces a previously queued change. A change with a fee creates the treasury
+    /// on first use.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1. `[writable]` treasury_account, PDA `[TREASURY_SEED, namespace]`
+    /// 2. `[signer, writable]` payer for the treasury rent
+    /// 3. `[]` Rent sysvar
+    /// 4. `[]` System program
+    /// 5. `[signer]` Admins of counters, as many as the threshold requires
+    QueueSettingsChange {
+        inc_step: u32,
+        dec_step: u32,
+        saturating: bool,
+        min_value: i64,
+        max_value: i64,
+        delay: u32,
+        fee_lamports: u64,
+        max_delta: u64,
+        min_interval: u64,
+    },
+
+    /// Apply the queued settings change once the delay has passed. Anyone can do it.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    ExecuteSettingsChange,
+
+    /// Drop the queued settings change.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1.. `[signer]` Admins of counters
+    CancelSettingsChange,
+
+    /// Stop all counter changes. Either the guardian or the admins can do it.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1.. `[signer]` Guardian or admins of counters
+    Pause,
+
+    /// Allow counter changes again. Only the admins can do it.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1.. `[signer]` Admins of counters
+    Unpause,
+
+    /// Set the guardian which can pause the program. Zeroes remove the guardian.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1.. `[signer]` Admins of counters
+    SetGuardian { guardian: [u8; 32] },
+
+    /// Add an arbitrary positive amount to a counter, at most `max_delta` of the settings.
+    /// Returns the updated counter.
+    /// Accounts:
+    /// 0. `[signer, writable]` owner or operator of a counter, pays the fee and the rate limit
+    ///    account
+    /// 1. `[writable]` counter_account, PDA derived from the owner with the label
+    /// 2. `[]` settings_account of the counter namespace, PDA
+    /// 3. `[writable]` treasury_account of the namespace, only needed if there is a fee
+    /// 4. `[]` System program, only needed if there is a fee or the rate limit account is created
+    /// 5. `[writable]` history_account of the counter, only needed if it has one
+    /// 6. `[writable]` stats_account, PDA `[STATS_SEED]`, only optional for a counter
+    ///    created before the stats, whose changes are left out then
+    /// 7. `[writable]` leaderboard_account of the namespace, only needed if it has one
+    /// 8. `[writable]` rate_limit_account of the owner, PDA `[RATE_LIMIT_SEED, namespace, owner]`,
+    ///    only needed if there is a min interval. Created by the first change of the owner
+    Add { amount: i64 },
+
+    /// Subtract an arbitrary positive amount from a counter, at most `max_delta` of the settings.
+    /// Returns the updated counter.
+    /// Accounts:
+    /// 0. `[signer, writable]` owner or operator of a counter, pays the fee and the rate limit
+    ///    account
+    /// 1. `[writable]` counter_account, PDA derived from the owner with the label
+    /// 2. `[]` settings_account of the counter namespace, PDA
+    /// 3. `[writable]` treasury_account of the namespace, only needed if there is a fee
+    /// 4. `[]` System program, only needed if there is a fee or the rate limit account is created
+    /// 5. `[writable]` history_account of the counter, only needed if it has one
+    /// 6. `[writable]` stats_account, PDA `[STATS_SEED]`, only optional for a counter
+    ///    created before the stats, whose changes are left out then
+    /// 7. `[writable]` leaderboard_account of the namespace, only needed if it has one
+    /// 8. `[writable]` rate_limit_account of the owner, PDA `[RATE_LIMIT_SEED, namespace, owner]`,
+    ///    only needed if there is a min interval. Created by the first change of the owner
+    Sub { amount: i64 },
+
+    /// Overwrite the value of a counter. Requires `threshold` signatures from the admin set.
+    /// Returns the updated counter.
+    /// Accounts:
+    /// 0. `[writable]` counter_account, PDA derived from the owner with the label
+    /// 1. `[]` settings_account of the counter namespace, PDA
+    /// 2. `[signer]` Admins of counters, as many as the threshold requires
+    /// 3. `[writable]` history_account of the counter after the admins, only needed if it has one
+    /// 4. `[writable]` stats_account, PDA `[STATS_SEED]`, only optional for a counter
+    ///    created before the stats, whose changes are left out then
+    /// 5. `[writable]` leaderboard_account of the namespace, only needed if it has one
+    Set { value: i64 },
+
+    /// Set the largest amount a single `Add` or `Sub` may change a counter by.
+    /// Only possible while the timelock delay is zero, otherwise use `QueueSettingsChange`.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1.. `[signer]` Admins of counters
+    SetMaxDelta { max_delta: u64 },
+
+    /// Apply 1 to `MAX_BATCH_OPS` operations to a counter at once. Either all of them
+    /// succeed or none is applied. An operator spends one allowance per operation.
+    /// Returns the updated counter.
+    /// Accounts:
+    /// 0. `[signer, writable]` owner or operator of a counter, pays the fee and the rate limit
+    ///    account
+    /// 1. `[writable]` counter_account, PDA derived from the owner with the label
+    /// 2. `[]` settings_account of the counter namespace, PDA
+    /// 3. `[writable]` treasury_account of the namespace, only needed if there is a fee
+    /// 4. `[]` System program, only needed if there is a fee or the rate limit account is created
+    /// 5. `[writable]` history_account of the counter, only needed if it has one
+    /// 6. `[writable]` stats_account, PDA `[STATS_SEED]`, only optional for a counter
+    ///    created before the stats, whose changes are left out then
+    /// 7. `[writable]` leaderboard_account of the namespace, only needed if it has one
+    /// 8. `[writable]` rate_limit_account of the owner, PDA `[RATE_LIMIT_SEED, namespace, owner]`,
+    ///    only needed if there is a min interval. Created by the first change of the owner
+    Batch { ops: Vec<CounterOp> },
+
+    /// Let an operator change a counter on behalf of its owner, at most `allowance` times.
+    /// Replaces the previous operator.
+    /// Accounts:
+    /// 0. `[signer]` owner of a counter
+    /// 1. `[writable]` counter_account, PDA derived from the owner with the label
+    ApproveOperator { operator: [u8; 32], allowance: u64 },
+
+    /// Remove the operator of a counter.
+    /// Accounts:
+    /// 0. `[signer]` owner of a counter
+    /// 1. `[writable]` counter_account, PDA derived from the owner with the label
+    RevokeOperator,
+
+    /// Set the number of slots an owner waits between changes of its counters, a `Batch` waits
+    /// them once per operation. The first change of an owner may only apply a single operation.
+    /// Only possible while the timelock delay is zero, otherwise use `QueueSettingsChange`.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1.. `[signer]` Admins of counters
+    SetMinInterval { min_interval: u64 },
+
+    /// Set the fee every counter operation pays to the treasury of the namespace.
+    /// Creates the treasury on first use.
+    /// Only possible while the timelock delay is zero, otherwise use `QueueSettingsChange`.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1. `[writable]` treasury_account, PDA `[TREASURY_SEED, namespace]`
+    /// 2. `[signer, writable]` payer for the treasury rent
+    /// 3. `[]` Rent sysvar
+    /// 4. `[]` System program
+    /// 5. `[signer]` Admins of counters, as many as the threshold requires
+    SetFee { fee_lamports: u64 },
+
+    /// Pay out collected fees. The treasury keeps its rent exempt balance.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1. `[writable]` treasury_account, PDA `[TREASURY_SEED, namespace]`
+    /// 2. `[writable]` destination account for the lamports
+    /// 3. `[signer]` Admins of counters, as many as the threshold requires
+    WithdrawTreasury { amount: u64 },
+
+    /// Return a counter without changing it, for programs calling this one with CPI.
+    /// Accounts:
+    /// 0. `[]` counter_account
+    GetCounter,
+
+    /// Create a history of a counter keeping its last `capacity` changes,
+    /// at most `MAX_HISTORY_CAPACITY`. The owner pays for the rent.
+    /// Accounts:
+    /// 0. `[signer, writable]` owner of a counter
+    /// 1. `[writable]` counter_account, PDA derived from the owner with the label
+    /// 2. `[writable]` history_account, PDA `[HISTORY_SEED, counter]`
+    /// 3. `[]` Rent sysvar
+    /// 4. `[]` System program
+    CreateHistory { capacity: u32 },
+
+    /// Create the stats account aggregating all counters. Changes are counted from now on.
+    /// Accounts:
+    /// 0. `[]` settings_account of the default namespace, PDA
+    /// 1. `[writable]` stats_account, PDA `[STATS_SEED]`
+    /// 2. `[signer, writable]` payer for the stats rent
+    /// 3. `[]` Rent sysvar
+    /// 4. `[]` System program
+    /// 5. `[signer]` Admins of the default namespace, as many as the threshold requires
+    InitializeStats,
+
+    /// Reset the operation count and the extremes of the stats.
+    /// Accounts:
+    /// 0. `[]` settings_account of the default namespace, PDA
+    /// 1. `[writable]` stats_account, PDA `[STATS_SEED]`
+    /// 2. `[signer]` Admins of the default namespace, as many as the threshold requires
+    ResetStats,
+
+    /// Set the number of counters on the leaderboard of the namespace, at most
+    /// `MAX_LEADERBOARD_SIZE`. Zero disables it. Creates the leaderboard on first use.
+    /// Accounts:
+    /// 0. `[writable]` settings_account, PDA
+    /// 1. `[writable]` leaderboard_account, PDA `[LEADERBOARD_SEED, namespace]`
+    /// 2. `[signer, writable]` payer for the leaderboard rent
+    /// 3. `[]` Rent sysvar
+    /// 4. `[]` System program
+    /// 5. `[signer]` Admins of counters, as many as the threshold requires
+    SetLeaderboardSize { size: u8 },
+
+    /// Make the upgrade authority of the program the only admin of legacy default namespace
+    /// settings, whose only admin is the zero key no one can sign for. Fails for any other
+    /// settings, the admins manage those.
+    /// Accounts:
+    /// 0. `[signer]` Upgrade authority of the program
+    /// 1. `[writable]` settings_account of the default namespace, PDA
+    /// 2. `[]` program_data_account of the upgradeable loader, PDA
+    ReclaimSettings,
+}
+
+/// A single counter change, the same as the instruction of the same name.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub enum CounterOp {
+    Inc,
+    Dec,
+    Add { amount: i64 },
+    Sub { amount: i64 },
 }
 
 impl CounterInstruction {
-    pub fn inc(user: &Pubkey) -> Instruction {
-        let counter_pubkey = Counter::get_counter_pubkey(user);
-        let (settings_pubkey, _) = Settings::get_settings_pubkey_with_bump();
+    pub fn inc(user: &Pubkey, namespace: &[u8; 32]) -> Instruction {
+        Self::inc_named(user, COUNTER_SEED, namespace)
+    }
+
+    pub fn inc_named(user: &Pubkey, label: &str, namespace: &[u8; 32]) -> Instruction {
         Instruction::new_with_borsh(
             id(),
             &CounterInstruction::Inc,
+            Self::counter_accounts(user, label, namespace),
+        )
+    }
+
+    pub fn dec(user: &Pubkey, namespace: &[u8; 32]) -> Instruction {
+        Self::dec_named(user, COUNTER_SEED, namespace)
+    }
+
+    pub fn dec_named(user: &Pubkey, label: &str, namespace: &[u8; 32]) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::Dec,
+            Self::counter_accounts(user, label, namespace),
+        )
+    }
+
+    pub fn create_counter(user: &Pubkey, namespace: &[u8; 32]) -> Instruction {
+        Self::create_named_counter(user, COUNTER_SEED, namespace)
+    }
+
+    pub fn create_named_counter(user: &Pubkey, label: &str, namespace: &[u8; 32]) -> Instruction {
+        let counter_pubkey = Counter::get_named_counter_pubkey(user, label);
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::CreateCounter { namespace: *namespace, label: label.to_string() },
             vec![
-                AccountMeta::new_readonly(*user, true),
+                AccountMeta::new(*user, true),
                 AccountMeta::new(counter_pubkey, false),
-                AccountMeta::new(settings_pubkey, false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(Stats::get_stats_pubkey(), false),
             ],
         )
     }
 
-    pub fn dec(user: &Pubkey) -> Instruction {
-        let counter_pubkey = Counter::get_counter_pubkey(user);
-        let (settings_pubkey, _) = Settings::get_settings_pubkey_with_bump();
+    pub fn close_counter(user: &Pubkey, namespace: &[u8; 32], destination: &Pubkey) -> Instruction {
+        Self::close_named_counter(user, COUNTER_SEED, namespace, destination)
+    }
+
+    pub fn close_named_counter(
+        user: &Pubkey,
+        label: &str,
+        namespace: &[u8; 32],
+        destination: &Pubkey,
+    ) -> Instruction {
+        let counter_pubkey = Counter::get_named_counter_pubkey(user, label);
         Instruction::new_with_borsh(
             id(),
-            &CounterInstruction::Dec,
+            &CounterInstruction::CloseCounter,
             vec![
                 AccountMeta::new_readonly(*user, true),
                 AccountMeta::new(counter_pubkey, false),
-                AccountMeta::new(settings_pubkey, false),
+                AccountMeta::new(*destination, false),
+                AccountMeta::new(History::get_history_pubkey(&counter_pubkey), false),
+                AccountMeta::new(Stats::get_stats_pubkey(), false),
+                AccountMeta::new(Leaderboard::get_leaderboard_pubkey(namespace), false),
+            ],
+        )
+    }
+
+    pub fn migrate(payer: &Pubkey, account: &Pubkey) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::Migrate,
+            vec![
+                AccountMeta::new(*payer, true),
+                AccountMeta::new(*account, false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
             ],
         )
     }
 
     pub fn update_settings(
-        admin: &Pubkey,
-        new_admin: [u8; 32],
+        namespace: &[u8; 32],
+        admins: &[Pubkey],
         inc_step: u32,
         dec_step: u32,
+        saturating: bool,
+        min_value: i64,
+        max_value: i64,
     ) -> Instruction {
-        let (settings_pubkey, _) = Settings::get_settings_pubkey_with_bump();
         Instruction::new_with_borsh(
             id(),
-            &CounterInstruction::UpdateSettings { admin: new_admin, inc_step, dec_step },
+            &CounterInstruction::UpdateSettings {
+                inc_step,
+                dec_step,
+                saturating,
+                min_value,
+                max_value,
+            },
+            Self::admin_accounts(namespace, admins),
+        )
+    }
+
+    pub fn propose_admin(
+        namespace: &[u8; 32],
+        admins: &[Pubkey],
+        new_admin: &Pubkey,
+    ) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::ProposeAdmin { new_admin: new_admin.to_bytes() },
+            Self::admin_accounts(namespace, admins),
+        )
+    }
+
+    pub fn accept_admin(namespace: &[u8; 32], new_admin: &Pubkey) -> Instruction {
+        let (settings_pubkey, _) = Settings::get_settings_pubkey_with_bump(namespace);
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::AcceptAdmin,
             vec![
-                AccountMeta::new(*admin, true),
+                AccountMeta::new_readonly(*new_admin, true),
                 AccountMeta::new(settings_pubkey, false),
+            ],
+        )
+    }
+
+    pub fn cancel_admin_proposal(namespace: &[u8; 32], admins: &[Pubkey]) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::CancelAdminProposal,
+            Self::admin_accounts(namespace, admins),
+        )
+    }
+
+    pub fn remove_admin(namespace: &[u8; 32], admins: &[Pubkey], admin: &Pubkey) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::RemoveAdmin { admin: admin.to_bytes() },
+            Self::admin_accounts(namespace, admins),
+        )
+    }
+
+    pub fn set_threshold(namespace: &[u8; 32], admins: &[Pubkey], threshold: u8) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::SetThreshold { threshold },
+            Self::admin_accounts(namespace, admins),
+        )
+    }
+
+    pub fn initialize_settings(
+        authority: &Pubkey,
+        namespace: &[u8; 32],
+        inc_step: u32,
+        dec_step: u32,
+        saturating: bool,
+        min_value: i64,
+        max_value: i64,
+    ) -> Instruction {
+        let (settings_pubkey, _) = Settings::get_settings_pubkey_with_bump(namespace);
+        let mut accounts = vec![
+            AccountMeta::new(*authority, true),
+            AccountMeta::new(settings_pubkey, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ];
+        if *namespace == DEFAULT_NAMESPACE {
+            accounts.push(AccountMeta::new_readonly(get_program_data_pubkey(), false));
+        }
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::InitializeSettings {
+                namespace: *namespace,
+                inc_step,
+                dec_step,
+                saturating,
+                min_value,
+                max_value,
+            },
+            accounts,
+        )
+    }
+
+    /// `payer` covers the treasury rent, it's only used if the change has a fee.
+    pub fn queue_settings_change(
+        namespace: &[u8; 32],
+        payer: &Pubkey,
+        admins: &[Pubkey],
+        change: SettingsChange,
+    ) -> Instruction {
+        let SettingsChange {
+            inc_step,
+            dec_step,
+            saturating,
+            min_value,
+            max_value,
+            delay,
+            fee_lamports,
+            max_delta,
+            min_interval,
+        } = change;
+        let accounts = Self::treasury_accounts(namespace, payer, admins);
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::QueueSettingsChange {
+                inc_step,
+                dec_step,
+                saturating,
+                min_value,
+                max_value,
+                delay,
+                fee_lamports,
+                max_delta,
+                min_interval,
+            },
+            accounts,
+        )
+    }
+
+    pub fn reclaim_settings(authority: &Pubkey) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::ReclaimSettings,
+            vec![
+                AccountMeta::new_readonly(*authority, true),
+                AccountMeta::new(Settings::get_settings_pubkey(&DEFAULT_NAMESPACE), false),
+                AccountMeta::new_readonly(get_program_data_pubkey(), false),
+            ],
+        )
+    }
+
+    pub fn execute_settings_change(namespace: &[u8; 32]) -> Instruction {
+        let (settings_pubkey, _) = Settings::get_settings_pubkey_with_bump(namespace);
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::ExecuteSettingsChange,
+            vec![AccountMeta::new(settings_pubkey, false)],
+        )
+    }
+
+    pub fn cancel_settings_change(namespace: &[u8; 32], admins: &[Pubkey]) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::CancelSettingsChange,
+            Self::admin_accounts(namespace, admins),
+        )
+    }
+
+    pub fn pause(namespace: &[u8; 32], signers: &[Pubkey]) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::Pause,
+            Self::admin_accounts(namespace, signers),
+        )
+    }
+
+    pub fn unpause(namespace: &[u8; 32], admins: &[Pubkey]) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::Unpause,
+            Self::admin_accounts(namespace, admins),
+        )
+    }
+
+    pub fn set_guardian(namespace: &[u8; 32], admins: &[Pubkey], guardian: &Pubkey) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::SetGuardian { guardian: guardian.to_bytes() },
+            Self::admin_accounts(namespace, admins),
+        )
+    }
+
+    pub fn add(user: &Pubkey, namespace: &[u8; 32], amount: i64) -> Instruction {
+        Self::add_named(user, COUNTER_SEED, namespace, amount)
+    }
+
+    pub fn add_named(user: &Pubkey, label: &str, namespace: &[u8; 32], amount: i64) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::Add { amount },
+            Self::counter_accounts(user, label, namespace),
+        )
+    }
+
+    pub fn sub(user: &Pubkey, namespace: &[u8; 32], amount: i64) -> Instruction {
+        Self::sub_named(user, COUNTER_SEED, namespace, amount)
+    }
+
+    pub fn sub_named(user: &Pubkey, label: &str, namespace: &[u8; 32], amount: i64) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::Sub { amount },
+            Self::counter_accounts(user, label, namespace),
+        )
+    }
+
+    pub fn batch(user: &Pubkey, namespace: &[u8; 32], ops: Vec<CounterOp>) -> Instruction {
+        Self::batch_named(user, COUNTER_SEED, namespace, ops)
+    }
+
+    pub fn batch_named(
+        user: &Pubkey,
+        label: &str,
+        namespace: &[u8; 32],
+        ops: Vec<CounterOp>,
+    ) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::Batch { ops },
+            Self::counter_accounts(user, label, namespace),
+        )
+    }
+
+    /// `Inc` signed by the operator of a counter instead of its owner.
+    pub fn inc_by_operator(
+        operator: &Pubkey,
+        user: &Pubkey,
+        label: &str,
+        namespace: &[u8; 32],
+    ) -> Instruction {
+        let mut ix = Self::inc_named(user, label, namespace);
+        ix.accounts[0] = AccountMeta::new(*operator, true);
+        ix
+    }
+
+    /// `Dec` signed by the operator of a counter instead of its owner.
+    pub fn dec_by_operator(
+        operator: &Pubkey,
+        user: &Pubkey,
+        label: &str,
+        namespace: &[u8; 32],
+    ) -> Instruction {
+        let mut ix = Self::dec_named(user, label, namespace);
+        ix.accounts[0] = AccountMeta::new(*operator, true);
+        ix
+    }
+
+    pub fn approve_operator(
+        user: &Pubkey,
+        label: &str,
+        operator: &Pubkey,
+        allowance: u64,
+    ) -> Instruction {
+        let counter_pubkey = Counter::get_named_counter_pubkey(user, label);
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::ApproveOperator { operator: operator.to_bytes(), allowance },
+            vec![AccountMeta::new_readonly(*user, true), AccountMeta::new(counter_pubkey, false)],
+        )
+    }
+
+    pub fn revoke_operator(user: &Pubkey, label: &str) -> Instruction {
+        let counter_pubkey = Counter::get_named_counter_pubkey(user, label);
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::RevokeOperator,
+            vec![AccountMeta::new_readonly(*user, true), AccountMeta::new(counter_pubkey, false)],
+        )
+    }
+
+    pub fn set(user: &Pubkey, namespace: &[u8; 32], admins: &[Pubkey], value: i64) -> Instruction {
+        Self::set_named(user, COUNTER_SEED, namespace, admins, value)
+    }
+
+    pub fn set_named(
+        user: &Pubkey,
+        label: &str,
+        namespace: &[u8; 32],
+        admins: &[Pubkey],
+        value: i64,
+    ) -> Instruction {
+        let counter_pubkey = Counter::get_named_counter_pubkey(user, label);
+        let (settings_pubkey, _) = Settings::get_settings_pubkey_with_bump(namespace);
+        let mut accounts = vec![
+            AccountMeta::new(counter_pubkey, false),
+            AccountMeta::new_readonly(settings_pubkey, false),
+        ];
+        accounts.extend(admins.iter().map(|admin| AccountMeta::new_readonly(*admin, true)));
+        accounts.push(AccountMeta::new(History::get_history_pubkey(&counter_pubkey), false));
+        accounts.push(AccountMeta::new(Stats::get_stats_pubkey(), false));
+        accounts.push(AccountMeta::new(Leaderboard::get_leaderboard_pubkey(namespace), false));
+        Instruction::new_with_borsh(id(), &CounterInstruction::Set { value }, accounts)
+    }
+
+    pub fn set_max_delta(namespace: &[u8; 32], admins: &[Pubkey], max_delta: u64) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::SetMaxDelta { max_delta },
+            Self::admin_accounts(namespace, admins),
+        )
+    }
+
+    pub fn set_min_interval(
+        namespace: &[u8; 32],
+        admins: &[Pubkey],
+        min_interval: u64,
+    ) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::SetMinInterval { min_interval },
+            Self::admin_accounts(namespace, admins),
+        )
+    }
+
+    pub fn set_fee(
+        namespace: &[u8; 32],
+        payer: &Pubkey,
+        admins: &[Pubkey],
+        fee_lamports: u64,
+    ) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::SetFee { fee_lamports },
+            Self::treasury_accounts(namespace, payer, admins),
+        )
+    }
+
+    /// Accounts of the instructions creating the treasury on first use.
+    fn treasury_accounts(
+        namespace: &[u8; 32],
+        payer: &Pubkey,
+        admins: &[Pubkey],
+    ) -> Vec<AccountMeta> {
+        let mut accounts = Self::admin_accounts(namespace, admins);
+        accounts.splice(
+            1..1,
+            [
+                AccountMeta::new(Settings::get_treasury_pubkey(namespace), false),
+                AccountMeta::new(*payer, true),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+        );
+        accounts
+    }
+
+    pub fn withdraw_treasury(
+        namespace: &[u8; 32],
+        admins: &[Pubkey],
+        destination: &Pubkey,
+        amount: u64,
+    ) -> Instruction {
+        let mut accounts = Self::admin_accounts(namespace, admins);
+        accounts.splice(
+            1..1,
+            [
+                AccountMeta::new(Settings::get_treasury_pubkey(namespace), false),
+                AccountMeta::new(*destination, false),
+            ],
+        );
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::WithdrawTreasury { amount },
+            accounts,
+        )
+    }
+
+    pub fn get_counter(counter: &Pubkey) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::GetCounter,
+            vec![AccountMeta::new_readonly(*counter, false)],
+        )
+    }
+
+    pub fn create_history(user: &Pubkey, label: &str, capacity: u32) -> Instruction {
+        let counter_pubkey = Counter::get_named_counter_pubkey(user, label);
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::CreateHistory { capacity },
+            vec![
+                AccountMeta::new(*user, true),
+                AccountMeta::new(counter_pubkey, false),
+                AccountMeta::new(History::get_history_pubkey(&counter_pubkey), false),
                 AccountMeta::new_readonly(sysvar::rent::id(), false),
                 AccountMeta::new_readonly(system_program::id(), false),
             ],
         )
     }
+
+    pub fn initialize_stats(payer: &Pubkey, admins: &[Pubkey]) -> Instruction {
+        let mut accounts = Self::admin_accounts(&DEFAULT_NAMESPACE, admins);
+        accounts[0].is_writable = false;
+        accounts.splice(
+            1..1,
+            [
+                AccountMeta::new(Stats::get_stats_pubkey(), false),
+                AccountMeta::new(*payer, true),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+        );
+        Instruction::new_with_borsh(id(), &CounterInstruction::InitializeStats, accounts)
+    }
+
+    pub fn reset_stats(admins: &[Pubkey]) -> Instruction {
+        let mut accounts = Self::admin_accounts(&DEFAULT_NAMESPACE, admins);
+        accounts[0].is_writable = false;
+        accounts.insert(1, AccountMeta::new(Stats::get_stats_pubkey(), false));
+        Instruction::new_with_borsh(id(), &CounterInstruction::ResetStats, accounts)
+    }
+
+    pub fn set_leaderboard_size(
+        namespace: &[u8; 32],
+        payer: &Pubkey,
+        admins: &[Pubkey],
+        size: u8,
+    ) -> Instruction {
+        let mut accounts = Self::admin_accounts(namespace, admins);
+        accounts.splice(
+            1..1,
+            [
+                AccountMeta::new(Leaderboard::get_leaderboard_pubkey(namespace), false),
+                AccountMeta::new(*payer, true),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+        );
+        Instruction::new_with_borsh(
+            id(),
+            &CounterInstruction::SetLeaderboardSize { size },
+            accounts,
+        )
+    }
+
+    /// Accounts of the instructions changing a counter, the owner pays the fee.
+    /// The history, leaderboard and rate limit addresses are passed whether or not the accounts
+    /// exist.
+    fn counter_accounts(user: &Pubkey, label: &str, namespace: &[u8; 32]) -> Vec<AccountMeta> {
+        let counter_pubkey = Counter::get_named_counter_pubkey(user, label);
+        vec![
+            AccountMeta::new(*user, true),
+            AccountMeta::new(counter_pubkey, false),
+            AccountMeta::new_readonly(Settings::get_settings_pubkey(namespace), false),
+            AccountMeta::new(Settings::get_treasury_pubkey(namespace), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(History::get_history_pubkey(&counter_pubkey), false),
+            AccountMeta::new(Stats::get_stats_pubkey(), false),
+            AccountMeta::new(Leaderboard::get_leaderboard_pubkey(namespace), false),
+            AccountMeta::new(RateLimit::get_rate_limit_pubkey(namespace, user), false),
+        ]
+    }
+
+    /// Settings account followed by the signing admins.
+    fn admin_accounts(namespace: &[u8; 32], admins: &[Pubkey]) -> Vec<AccountMeta> {
+        let (settings_pubkey, _) = Settings::get_settings_pubkey_with_bump(namespace);
+        let mut accounts = vec![AccountMeta::new(settings_pubkey, false)];
+        accounts.extend(admins.iter().map(|admin| AccountMeta::new_readonly(*admin, true)));
+        accounts
+    }
 }
 
 #[cfg(test)]
@@ -91,26 +931,59 @@ mod test {
     use borsh::BorshSerialize;
     use solana_program::pubkey::Pubkey;
 
-    use crate::instruction::CounterInstruction;
+    use crate::instruction::{CounterInstruction, CounterOp};
 
     #[test]
     fn test_serialization() {
         let data = CounterInstruction::UpdateSettings {
-            admin: Pubkey::from_str("EG7uy9FCe4AxL9AavEA1nXDfo2AoBo1ZtBCV224hmoub")
-                .unwrap()
-                .to_bytes(),
             inc_step: 19,
             dec_step: 99,
+            saturating: false,
+            min_value: -1,
+            max_value: 1000,
+        }
+        .try_to_vec()
+        .unwrap();
+        assert_eq!(
+            data,
+            [
+                2, 19, 0, 0, 0, 99, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 232, 3, 0,
+                0, 0, 0, 0, 0
+            ]
+        );
+
+        let data = CounterInstruction::ProposeAdmin {
+            new_admin: Pubkey::from_str("EG7uy9FCe4AxL9AavEA1nXDfo2AoBo1ZtBCV224hmoub")
+                .unwrap()
+                .to_bytes(),
         }
         .try_to_vec()
         .unwrap();
         assert_eq!(
             data,
             [
-                2, 197, 7, 117, 129, 90, 151, 178, 48, 248, 208, 199, 5, 17, 134, 51, 183, 155,
-                153, 209, 86, 177, 138, 127, 133, 1, 191, 178, 128, 179, 23, 157, 98, 19, 0, 0, 0,
-                99, 0, 0, 0
+                6, 197, 7, 117, 129, 90, 151, 178, 48, 248, 208, 199, 5, 17, 134, 51, 183, 155,
+                153, 209, 86, 177, 138, 127, 133, 1, 191, 178, 128, 179, 23, 157, 98
             ]
         );
+
+        let data =
+            CounterInstruction::CreateCounter { namespace: [1; 32], label: "ab".to_string() }
+                .try_to_vec()
+                .unwrap();
+        assert_eq!(data, [&[3][..], &[1; 32], &[2, 0, 0, 0, 97, 98]].concat());
+
+        let data = CounterInstruction::SetThreshold { threshold: 2 }.try_to_vec().unwrap();
+        assert_eq!(data, [11, 2]);
+
+        let data = CounterInstruction::Sub { amount: 300 }.try_to_vec().unwrap();
+        assert_eq!(data, [19, 44, 1, 0, 0, 0, 0, 0, 0]);
+
+        let data = CounterInstruction::Batch {
+            ops: vec![CounterOp::Inc, CounterOp::Dec, CounterOp::Add { amount: 5 }],
+        }
+        .try_to_vec()
+        .unwrap();
+        assert_eq!(data, [22, 3, 0, 0, 0, 0, 1, 2, 5, 0, 0, 0, 0, 0, 0, 0]);
     }
 }
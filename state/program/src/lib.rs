@@ -1,4 +1,7 @@
+use solana_program::{bpf_loader_upgradeable, pubkey::Pubkey};
+
 pub mod error;
+pub mod event;
 pub mod instruction;
 pub mod processor;
 pub mod state;
@@ -6,6 +9,24 @@ pub mod state;
 #[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
 
+// for programs depending on this one, the functional tests use it too
+#[cfg(any(feature = "no-entrypoint", feature = "test-bpf"))]
+pub mod cpi;
+
 pub const COUNTER_SEED: &str = "counter";
 pub const SETTINGS_SEED: &str = "settings";
+pub const TREASURY_SEED: &str = "treasury";
+pub const HISTORY_SEED: &str = "history";
+pub const STATS_SEED: &str = "stats";
+pub const LEADERBOARD_SEED: &str = "leaderboard";
+pub const RATE_LIMIT_SEED: &str = "rate_limit";
+
+/// Namespace of the settings account created before namespaces were introduced.
+pub const DEFAULT_NAMESPACE: [u8; 32] = [0; 32];
 solana_program::declare_id!("9onZvMzqAFzSHJrLNVWfqLRFFQ5ZCGzNXB4PBxmp6z5Y");
+
+/// Account of the upgradeable loader holding the program upgrade authority.
+pub fn get_program_data_pubkey() -> Pubkey {
+    let (pubkey, _) = Pubkey::find_program_address(&[id().as_ref()], &bpf_loader_upgradeable::id());
+    pubkey
+}
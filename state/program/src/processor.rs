@@ -1,16 +1,25 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::account_info::{next_account_info, AccountInfo};
+use solana_program::bpf_loader_upgradeable::{self, UpgradeableLoaderState};
 use solana_program::entrypoint::ProgramResult;
-use solana_program::program::invoke_signed;
+use solana_program::program::{invoke, invoke_signed, set_return_data};
 use solana_program::program_error::ProgramError;
+use solana_program::program_utils::limited_deserialize;
 use solana_program::pubkey::Pubkey;
-use solana_program::sysvar::{rent::Rent, Sysvar};
-use solana_program::{msg, system_instruction};
+use solana_program::sysvar::{clock::Clock, rent::Rent, Sysvar};
+use solana_program::{msg, system_instruction, system_program};
 
 use crate::error::CounterError;
-use crate::instruction::CounterInstruction;
-use crate::state::{Counter, Settings};
-use crate::{id, SETTINGS_SEED};
+use crate::event::CounterEvent;
+use crate::instruction::{CounterInstruction, CounterOp, MAX_BATCH_OPS};
+use crate::state::{
+    load_account, Counter, History, HistoryEntry, Leaderboard, LeaderboardEntry, RateLimit,
+    Settings, SettingsChange, Stats, Versioned, MAX_ADMINS, MAX_LEADERBOARD_SIZE,
+};
+use crate::{
+    get_program_data_pubkey, id, DEFAULT_NAMESPACE, HISTORY_SEED, LEADERBOARD_SEED,
+    RATE_LIMIT_SEED, STATS_SEED, TREASURY_SEED,
+};
 
 pub struct Processor;
 
@@ -21,128 +30,1398 @@ impl Processor {
         match instruction {
             CounterInstruction::Inc => Self::process_inc(accounts),
             CounterInstruction::Dec => Self::process_dec(accounts),
-            CounterInstruction::UpdateSettings { admin, inc_step, dec_step } => {
-                Self::process_update_settings(accounts, admin, inc_step, dec_step)
+            CounterInstruction::UpdateSettings {
+                inc_step,
+                dec_step,
+                saturating,
+                min_value,
+                max_value,
+            } => Self::process_update_settings(
+                accounts, inc_step, dec_step, saturating, min_value, max_value,
+            ),
+            CounterInstruction::CreateCounter { namespace, label } => {
+                Self::process_create_counter(accounts, namespace, label)
             }
+            CounterInstruction::CloseCounter => Self::process_close_counter(accounts),
+            CounterInstruction::Migrate => Self::process_migrate(accounts),
+            CounterInstruction::ProposeAdmin { new_admin } => {
+                Self::process_propose_admin(accounts, new_admin)
+            }
+            CounterInstruction::AcceptAdmin => Self::process_accept_admin(accounts),
+            CounterInstruction::CancelAdminProposal => {
+                Self::process_cancel_admin_proposal(accounts)
+            }
+            CounterInstruction::InitializeSettings {
+                namespace,
+                inc_step,
+                dec_step,
+                saturating,
+                min_value,
+                max_value,
+            } => Self::process_initialize_settings(
+                accounts, namespace, inc_step, dec_step, saturating, min_value, max_value,
+            ),
+            CounterInstruction::RemoveAdmin { admin } => {
+                Self::process_remove_admin(accounts, admin)
+            }
+            CounterInstruction::SetThreshold { threshold } => {
+                Self::process_set_threshold(accounts, threshold)
+            }
+            CounterInstruction::QueueSettingsChange {
+                inc_step,
+                dec_step,
+                saturating,
+                min_value,
+                max_value,
+                delay,
+                fee_lamports,
+                max_delta,
+                min_interval,
+            } => Self::process_queue_settings_change(
+                accounts,
+                SettingsChange {
+                    inc_step,
+                    dec_step,
+                    saturating,
+                    min_value,
+                    max_value,
+                    delay,
+                    fee_lamports,
+                    max_delta,
+                    min_interval,
+                },
+            ),
+            CounterInstruction::ExecuteSettingsChange => {
+                Self::process_execute_settings_change(accounts)
+            }
+            CounterInstruction::CancelSettingsChange => {
+                Self::process_cancel_settings_change(accounts)
+            }
+            CounterInstruction::Pause => Self::process_pause(accounts),
+            CounterInstruction::Unpause => Self::process_unpause(accounts),
+            CounterInstruction::SetGuardian { guardian } => {
+                Self::process_set_guardian(accounts, guardian)
+            }
+            CounterInstruction::Add { amount } => Self::process_add(accounts, amount),
+            CounterInstruction::Sub { amount } => Self::process_sub(accounts, amount),
+            CounterInstruction::Set { value } => Self::process_set(accounts, value),
+            CounterInstruction::SetMaxDelta { max_delta } => {
+                Self::process_set_max_delta(accounts, max_delta)
+            }
+            CounterInstruction::Batch { ops } => Self::process_batch(accounts, ops),
+            CounterInstruction::ApproveOperator { operator, allowance } => {
+                Self::process_approve_operator(accounts, operator, allowance)
+            }
+            CounterInstruction::RevokeOperator => Self::process_revoke_operator(accounts),
+            CounterInstruction::SetMinInterval { min_interval } => {
+                Self::process_set_min_interval(accounts, min_interval)
+            }
+            CounterInstruction::SetFee { fee_lamports } => {
+                Self::process_set_fee(accounts, fee_lamports)
+            }
+            CounterInstruction::WithdrawTreasury { amount } => {
+                Self::process_withdraw_treasury(accounts, amount)
+            }
+            CounterInstruction::GetCounter => Self::process_get_counter(accounts),
+            CounterInstruction::CreateHistory { capacity } => {
+                Self::process_create_history(accounts, capacity)
+            }
+            CounterInstruction::InitializeStats => Self::process_initialize_stats(accounts),
+            CounterInstruction::ResetStats => Self::process_reset_stats(accounts),
+            CounterInstruction::SetLeaderboardSize { size } => {
+                Self::process_set_leaderboard_size(accounts, size)
+            }
+            CounterInstruction::ReclaimSettings => Self::process_reclaim_settings(accounts),
         }
     }
 
     fn process_inc(accounts: &[AccountInfo]) -> ProgramResult {
         msg!("process_inc");
+        Self::change_counter(accounts, &[CounterOp::Inc])?;
+        msg!("process_inc: done");
+        Ok(())
+    }
+
+    fn process_dec(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_dec");
+        Self::change_counter(accounts, &[CounterOp::Dec])?;
+        msg!("process_dec: done");
+        Ok(())
+    }
+
+    fn process_add(accounts: &[AccountInfo], amount: i64) -> ProgramResult {
+        msg!("process_add");
+        Self::change_counter(accounts, &[CounterOp::Add { amount }])?;
+        msg!("process_add: done");
+        Ok(())
+    }
+
+    fn process_sub(accounts: &[AccountInfo], amount: i64) -> ProgramResult {
+        msg!("process_sub");
+        Self::change_counter(accounts, &[CounterOp::Sub { amount }])?;
+        msg!("process_sub: done");
+        Ok(())
+    }
+
+    fn process_batch(accounts: &[AccountInfo], ops: Vec<CounterOp>) -> ProgramResult {
+        msg!("process_batch: ops={}", ops.len());
+        if ops.is_empty() {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        if ops.len() > MAX_BATCH_OPS {
+            return Err(CounterError::TooManyOps.into());
+        }
+        Self::change_counter(accounts, &ops)?;
+        msg!("process_batch: done");
+        Ok(())
+    }
+
+    fn process_approve_operator(
+        accounts: &[AccountInfo],
+        operator: [u8; 32],
+        allowance: u64,
+    ) -> ProgramResult {
+        msg!("process_approve_operator: operator={:?}, allowance={}", operator, allowance);
         let acc_iter = &mut accounts.iter();
         let user_info = next_account_info(acc_iter)?;
         let counter_info = next_account_info(acc_iter)?;
+
+        let mut counter = Self::load_owned_counter(user_info, counter_info)?;
+        // the rate limit of the owner applies to the operator, it needs to be known
+        counter.owner = user_info.key.to_bytes();
+        counter.operator = operator;
+        counter.allowance = allowance;
+
+        let _ = counter.serialize(&mut &mut counter_info.data.borrow_mut()[..]);
+        msg!("process_approve_operator: done");
+        Ok(())
+    }
+
+    fn process_revoke_operator(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_revoke_operator");
+        let acc_iter = &mut accounts.iter();
+        let user_info = next_account_info(acc_iter)?;
+        let counter_info = next_account_info(acc_iter)?;
+
+        let mut counter = Self::load_owned_counter(user_info, counter_info)?;
+        counter.operator = [0; 32];
+        counter.allowance = 0;
+
+        let _ = counter.serialize(&mut &mut counter_info.data.borrow_mut()[..]);
+        msg!("process_revoke_operator: done");
+        Ok(())
+    }
+
+    fn process_set(accounts: &[AccountInfo], value: i64) -> ProgramResult {
+        msg!("process_set: value={}", value);
+        let acc_iter = &mut accounts.iter();
+        let counter_info = next_account_info(acc_iter)?;
+        let settings_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        let mut counter = load_account::<Counter>(counter_info)?;
+        let settings = Self::load_settings(settings_info)?;
+        if settings.namespace != counter.namespace {
+            return Err(CounterError::WrongSettingsPDA.into());
+        }
+        Self::check_admin(&settings, admin_infos)?;
+        settings.check_not_paused()?;
+        let history_info = Self::find_history(&counter, admin_infos)?;
+        let mut stats = Self::load_stats(counter.in_stats, admin_infos)?;
+        let mut leaderboard = Self::load_leaderboard(&settings, admin_infos)?;
+
+        let old_value = counter.value;
+        counter.set_value(value, settings.saturating)?;
+        settings.check_bounds(counter.value)?;
+        if let Some(history_info) = history_info {
+            let mut history = History::unpack_header(&history_info.data.borrow())?;
+            let signer = admin_infos
+                .iter()
+                .find(|info| info.is_signer && settings.is_admin(info.key))
+                .ok_or(CounterError::AdminRequired)?;
+            let entry = HistoryEntry {
+                delta: counter.value.saturating_sub(old_value),
+                slot: Clock::get()?.slot,
+                signer: signer.key.to_bytes(),
+            };
+            history.push(&mut history_info.data.borrow_mut(), &entry)?;
+        }
+        CounterEvent::Set {
+            counter: counter_info.key.to_bytes(),
+            old: old_value,
+            value: counter.value,
+        }
+        .emit();
+
+        counter.ranked |= leaderboard.is_some();
+        let _ = counter.serialize(&mut &mut counter_info.data.borrow_mut()[..]);
+        if let Some((stats_info, stats)) = &mut stats {
+            stats.record_change(&counter, old_value);
+            let _ = stats.serialize(&mut &mut stats_info.data.borrow_mut()[..]);
+        }
+        if let Some((leaderboard_info, leaderboard)) = &mut leaderboard {
+            let entry = LeaderboardEntry {
+                counter: counter_info.key.to_bytes(),
+                owner: counter.owner,
+                value: counter.value,
+            };
+            leaderboard.update(entry, settings.leaderboard_size as usize);
+            let _ = leaderboard.serialize(&mut &mut leaderboard_info.data.borrow_mut()[..]);
+        }
+        set_return_data(&counter.try_to_vec()?);
+        msg!("process_set: done");
+        Ok(())
+    }
+
+    fn process_update_settings(
+        accounts: &[AccountInfo],
+        inc_step: u32,
+        dec_step: u32,
+        saturating: bool,
+        min_value: i64,
+        max_value: i64,
+    ) -> ProgramResult {
+        msg!("process_update_settings");
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        if min_value > max_value {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let mut settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        if settings.delay > 0 {
+            return Err(CounterError::TimelockRequired.into());
+        }
+        let old = settings.values();
+        settings.inc_step = inc_step;
+        settings.dec_step = dec_step;
+        settings.saturating = saturating;
+        settings.min_value = min_value;
+        settings.max_value = max_value;
+        CounterEvent::SettingsUpdated {
+            settings: settings_info.key.to_bytes(),
+            old,
+            new: settings.values(),
+        }
+        .emit();
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_update_settings: done");
+        Ok(())
+    }
+
+    fn process_initialize_settings(
+        accounts: &[AccountInfo],
+        namespace: [u8; 32],
+        inc_step: u32,
+        dec_step: u32,
+        saturating: bool,
+        min_value: i64,
+        max_value: i64,
+    ) -> ProgramResult {
+        msg!(
+            "process_initialize_settings: namespace={:?}, inc_step={}, dec_step={}, \
+            saturating={}, min_value={}, max_value={}",
+            namespace,
+            inc_step,
+            dec_step,
+            saturating,
+            min_value,
+            max_value
+        );
+        let acc_iter = &mut accounts.iter();
+        let authority_info = next_account_info(acc_iter)?;
         let settings_info = next_account_info(acc_iter)?;
+        let rent_info = next_account_info(acc_iter)?;
+        let system_program_info = next_account_info(acc_iter)?;
+
+        let (settings_pubkey, bump_seed) = Settings::get_settings_pubkey_with_bump(&namespace);
+        if settings_pubkey != *settings_info.key {
+            return Err(CounterError::WrongSettingsPDA.into());
+        }
+        if namespace == DEFAULT_NAMESPACE {
+            let program_data_info = next_account_info(acc_iter)?;
+            Self::check_upgrade_authority(program_data_info, authority_info)?;
+        } else if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if !settings_info.data_is_empty() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        if min_value > max_value {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let mut admins = [[0; 32]; MAX_ADMINS];
+        admins[0] = authority_info.key.to_bytes();
+        let settings = Settings {
+            admins,
+            admin_count: 1,
+            threshold: 1,
+            inc_step,
+            dec_step,
+            saturating,
+            min_value,
+            max_value,
+            namespace,
+            ..Settings::default()
+        };
+        let space = settings.try_to_vec()?.len();
+        let rent = &Rent::from_account_info(rent_info)?;
+        let bump_seed = [bump_seed];
+        let mut signer_seeds = Settings::settings_seeds(&namespace);
+        signer_seeds.push(&bump_seed);
+        Self::create_pda_account(
+            authority_info,
+            settings_info,
+            space,
+            rent,
+            system_program_info,
+            &signer_seeds,
+        )?;
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_initialize_settings: done");
+        Ok(())
+    }
+
+    fn process_create_counter(
+        accounts: &[AccountInfo],
+        namespace: [u8; 32],
+        label: String,
+    ) -> ProgramResult {
+        msg!("process_create_counter: namespace={:?}, label={}", namespace, label);
+        let acc_iter = &mut accounts.iter();
+        let user_info = next_account_info(acc_iter)?;
+        let counter_info = next_account_info(acc_iter)?;
+        let rent_info = next_account_info(acc_iter)?;
+        let system_program_info = next_account_info(acc_iter)?;
 
         // Checks
         if !user_info.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
         }
-        if !Counter::is_ok_counter_pubkey(user_info.key, counter_info.key) {
+        let packed_label = Counter::pack_label(&label)?;
+        let (counter_pubkey, bump_seed) =
+            Counter::get_named_counter_pubkey_with_bump(user_info.key, &label);
+        if counter_pubkey != *counter_info.key {
             return Err(CounterError::WrongCounterPDA.into());
         }
-        if !Settings::is_ok_settings_pubkey(settings_info.key) {
-            return Err(CounterError::WrongSettingsPDA.into());
+        if !counter_info.data_is_empty() {
+            return Err(ProgramError::AccountAlreadyInitialized);
         }
+        let mut stats = Self::load_stats(true, acc_iter.as_slice())?;
 
-        let settings = Settings::try_from_slice(&settings_info.data.borrow())?;
-        let mut counter = Counter::try_from_slice(&counter_info.data.borrow())?;
-
-        counter.value += settings.inc_step as i64;
-        counter.counter += 1;
+        let mut counter = Counter {
+            namespace,
+            label: packed_label,
+            bump: bump_seed,
+            owner: user_info.key.to_bytes(),
+            ..Counter::default()
+        };
+        if let Some((_, stats)) = &mut stats {
+            stats.record_create(&mut counter);
+        }
+        let space = counter.try_to_vec()?.len();
+        let rent = &Rent::from_account_info(rent_info)?;
+        let bump_seed = [bump_seed];
+        let mut signer_seeds = Counter::counter_seeds(user_info.key, &label);
+        signer_seeds.push(&bump_seed);
+        Self::create_pda_account(
+            user_info,
+            counter_info,
+            space,
+            rent,
+            system_program_info,
+            &signer_seeds,
+        )?;
 
         let _ = counter.serialize(&mut &mut counter_info.data.borrow_mut()[..]);
-        msg!("process_inc: done");
+        if let Some((stats_info, stats)) = &stats {
+            let _ = stats.serialize(&mut &mut stats_info.data.borrow_mut()[..]);
+        }
+        msg!("process_create_counter: done");
         Ok(())
     }
 
-    fn process_dec(accounts: &[AccountInfo]) -> ProgramResult {
-        msg!("process_dec");
+    fn process_close_counter(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_close_counter");
         let acc_iter = &mut accounts.iter();
         let user_info = next_account_info(acc_iter)?;
         let counter_info = next_account_info(acc_iter)?;
-        let settings_info = next_account_info(acc_iter)?;
+        let destination_info = next_account_info(acc_iter)?;
 
         // Checks
         if !user_info.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
         }
-        if !Counter::is_ok_counter_pubkey(user_info.key, counter_info.key) {
+        if counter_info.owner != &id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        // legacy counters may be closed without a migration, it's done in memory only
+        let data = Counter::migrate(&counter_info.data.borrow())?;
+        let counter = Counter::unpack(&data)?;
+        if !counter.is_ok_counter_pubkey(user_info.key, counter_info.key) {
             return Err(CounterError::WrongCounterPDA.into());
         }
-        if !Settings::is_ok_settings_pubkey(settings_info.key) {
+
+        let mut stats = Self::load_stats(counter.in_stats, acc_iter.as_slice())?;
+        // only a counter changed along with a leaderboard may have an entry
+        let leaderboard_info = if counter.ranked {
+            let leaderboard_pubkey = Leaderboard::get_leaderboard_pubkey(&counter.namespace);
+            let leaderboard_info = acc_iter
+                .as_slice()
+                .iter()
+                .find(|info| *info.key == leaderboard_pubkey)
+                .ok_or(CounterError::LeaderboardRequired)?;
+            Some(leaderboard_info)
+        } else {
+            None
+        };
+
+        if let Some(history_info) = Self::find_history(&counter, acc_iter.as_slice())? {
+            Self::close_account(history_info, destination_info)?;
+        }
+        Self::close_account(counter_info, destination_info)?;
+        if let Some((stats_info, stats)) = &mut stats {
+            stats.record_close(&counter);
+            let _ = stats.serialize(&mut &mut stats_info.data.borrow_mut()[..]);
+        }
+        // a migrated counter may be from a namespace that never had a leaderboard
+        if let Some(leaderboard_info) = leaderboard_info {
+            if !Self::is_uninitialized(leaderboard_info) {
+                let mut leaderboard = load_account::<Leaderboard>(leaderboard_info)?;
+                leaderboard.remove(&counter_info.key.to_bytes());
+                let _ = leaderboard.serialize(&mut &mut leaderboard_info.data.borrow_mut()[..]);
+            }
+        }
+
+        msg!("process_close_counter: done");
+        Ok(())
+    }
+
+    fn process_migrate(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_migrate");
+        let acc_iter = &mut accounts.iter();
+        let payer_info = next_account_info(acc_iter)?;
+        let account_info = next_account_info(acc_iter)?;
+        let rent_info = next_account_info(acc_iter)?;
+        let system_program_info = next_account_info(acc_iter)?;
+
+        // Checks
+        if !payer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if account_info.owner != &id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let data = account_info.data.borrow().to_vec();
+        let new_data = if Settings::version_of(&data).is_ok() {
+            Settings::migrate(&data)?
+        } else {
+            Counter::migrate(&data)?
+        };
+        if new_data == data {
+            msg!("process_migrate: account is up to date");
+            return Ok(());
+        }
+
+        let rent = &Rent::from_account_info(rent_info)?;
+        let lamports = rent.minimum_balance(new_data.len()).saturating_sub(account_info.lamports());
+        if lamports > 0 {
+            invoke(
+                &system_instruction::transfer(payer_info.key, account_info.key, lamports),
+                &[payer_info.clone(), account_info.clone(), system_program_info.clone()],
+            )?;
+        }
+
+        account_info.realloc(new_data.len(), false)?;
+        account_info.data.borrow_mut().copy_from_slice(&new_data);
+        msg!("process_migrate: done");
+        Ok(())
+    }
+
+    fn process_propose_admin(accounts: &[AccountInfo], new_admin: [u8; 32]) -> ProgramResult {
+        msg!("process_propose_admin: new_admin={:?}", new_admin);
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        if new_admin == [0; 32] {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let mut settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        if settings.admins().contains(&new_admin) {
+            return Err(ProgramError::InvalidArgument);
+        }
+        settings.pending_admin = new_admin;
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_propose_admin: done");
+        Ok(())
+    }
+
+    fn process_accept_admin(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_accept_admin");
+        let acc_iter = &mut accounts.iter();
+        let new_admin_info = next_account_info(acc_iter)?;
+        let settings_info = next_account_info(acc_iter)?;
+
+        if !new_admin_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mut settings = Self::load_settings(settings_info)?;
+        if settings.pending_admin == [0; 32]
+            || settings.pending_admin != new_admin_info.key.to_bytes()
+        {
+            return Err(CounterError::PendingAdminRequired.into());
+        }
+        settings.add_admin(settings.pending_admin)?;
+        settings.pending_admin = [0; 32];
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_accept_admin: done");
+        Ok(())
+    }
+
+    fn process_cancel_admin_proposal(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_cancel_admin_proposal");
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        let mut settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        settings.pending_admin = [0; 32];
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_cancel_admin_proposal: done");
+        Ok(())
+    }
+
+    fn process_reclaim_settings(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_reclaim_settings");
+        let acc_iter = &mut accounts.iter();
+        let authority_info = next_account_info(acc_iter)?;
+        let settings_info = next_account_info(acc_iter)?;
+        let program_data_info = next_account_info(acc_iter)?;
+
+        Self::check_upgrade_authority(program_data_info, authority_info)?;
+        let mut settings = Self::load_settings(settings_info)?;
+        if settings.namespace != DEFAULT_NAMESPACE {
             return Err(CounterError::WrongSettingsPDA.into());
         }
+        if settings.admins() != [[0; 32]] {
+            return Err(CounterError::SettingsNotReclaimable.into());
+        }
+        settings.admins[0] = authority_info.key.to_bytes();
+        settings.threshold = 1;
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_reclaim_settings: done");
+        Ok(())
+    }
 
-        let settings = Settings::try_from_slice(&settings_info.data.borrow())?;
-        let mut counter = Counter::try_from_slice(&counter_info.data.borrow())?;
+    fn process_remove_admin(accounts: &[AccountInfo], admin: [u8; 32]) -> ProgramResult {
+        msg!("process_remove_admin: admin={:?}", admin);
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
 
-        counter.value -= settings.dec_step as i64;
-        counter.counter += 1;
+        let mut settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        settings.remove_admin(admin)?;
 
-        let _ = counter.serialize(&mut &mut counter_info.data.borrow_mut()[..]);
-        msg!("process_dec: done");
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_remove_admin: done");
+        Ok(())
+    }
+
+    fn process_set_threshold(accounts: &[AccountInfo], threshold: u8) -> ProgramResult {
+        msg!("process_set_threshold: threshold={}", threshold);
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
 
+        let mut settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        settings.set_threshold(threshold)?;
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_set_threshold: done");
         Ok(())
     }
 
-    fn process_update_settings(
+    fn process_queue_settings_change(
         accounts: &[AccountInfo],
-        admin: [u8; 32],
-        inc_step: u32,
-        dec_step: u32,
+        change: SettingsChange,
     ) -> ProgramResult {
-        msg!(
-            "process_update_settings: admin={:?} inc_step={}, dec_step={}",
-            admin,
-            inc_step,
-            dec_step
-        );
+        msg!("process_queue_settings_change: {:?}", change);
         let acc_iter = &mut accounts.iter();
-        let admin_info = next_account_info(acc_iter)?;
         let settings_info = next_account_info(acc_iter)?;
+        let treasury_info = next_account_info(acc_iter)?;
+        let payer_info = next_account_info(acc_iter)?;
         let rent_info = next_account_info(acc_iter)?;
         let system_program_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
 
-        let (settings_pubkey, bump_seed) = Settings::get_settings_pubkey_with_bump();
-        if settings_pubkey != *settings_info.key {
+        if change.min_value > change.max_value {
             return Err(ProgramError::InvalidArgument);
         }
 
-        if !admin_info.is_signer {
-            return Err(ProgramError::MissingRequiredSignature);
+        let mut settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        if change.fee_lamports > 0 {
+            settings.treasury_bump = Self::create_treasury(
+                &settings,
+                treasury_info,
+                payer_info,
+                rent_info,
+                system_program_info,
+            )?;
+        }
+        let clock = Clock::get()?;
+        settings.queue_change(change, clock.unix_timestamp);
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_queue_settings_change: done, eta={}", settings.pending_eta);
+        Ok(())
+    }
+
+    fn process_execute_settings_change(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_execute_settings_change");
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+
+        let mut settings = Self::load_settings(settings_info)?;
+        let clock = Clock::get()?;
+        let old = settings.values();
+        settings.execute_change(clock.unix_timestamp)?;
+        CounterEvent::SettingsUpdated {
+            settings: settings_info.key.to_bytes(),
+            old,
+            new: settings.values(),
         }
+        .emit();
 
-        if settings_info.data_is_empty() {
-            msg!("Creating settings account");
-            let settings = Settings { admin: admin_info.key.to_bytes(), inc_step, dec_step };
-            let space = settings.try_to_vec()?.len();
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_execute_settings_change: done");
+        Ok(())
+    }
+
+    fn process_cancel_settings_change(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_cancel_settings_change");
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        let mut settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        if settings.pending_eta == 0 {
+            return Err(CounterError::NoPendingSettingsChange.into());
+        }
+        settings.cancel_change();
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_cancel_settings_change: done");
+        Ok(())
+    }
+
+    fn process_pause(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_pause");
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let signer_infos = acc_iter.as_slice();
+
+        let mut settings = Self::load_settings(settings_info)?;
+        let is_guardian =
+            signer_infos.iter().any(|info| info.is_signer && settings.is_guardian(info.key));
+        if !is_guardian {
+            Self::check_admin(&settings, signer_infos)?;
+        }
+        settings.paused = true;
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_pause: done");
+        Ok(())
+    }
+
+    fn process_unpause(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_unpause");
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        let mut settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        settings.paused = false;
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_unpause: done");
+        Ok(())
+    }
+
+    fn process_set_guardian(accounts: &[AccountInfo], guardian: [u8; 32]) -> ProgramResult {
+        msg!("process_set_guardian: guardian={:?}", guardian);
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        let mut settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        settings.guardian = guardian;
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_set_guardian: done");
+        Ok(())
+    }
+
+    fn process_set_max_delta(accounts: &[AccountInfo], max_delta: u64) -> ProgramResult {
+        msg!("process_set_max_delta: max_delta={}", max_delta);
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        let mut settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        if settings.delay > 0 {
+            return Err(CounterError::TimelockRequired.into());
+        }
+        let old = settings.values();
+        settings.max_delta = max_delta;
+        CounterEvent::SettingsUpdated {
+            settings: settings_info.key.to_bytes(),
+            old,
+            new: settings.values(),
+        }
+        .emit();
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_set_max_delta: done");
+        Ok(())
+    }
+
+    fn process_set_min_interval(accounts: &[AccountInfo], min_interval: u64) -> ProgramResult {
+        msg!("process_set_min_interval: min_interval={}", min_interval);
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        let mut settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        if settings.delay > 0 {
+            return Err(CounterError::TimelockRequired.into());
+        }
+        let old = settings.values();
+        settings.min_interval = min_interval;
+        CounterEvent::SettingsUpdated {
+            settings: settings_info.key.to_bytes(),
+            old,
+            new: settings.values(),
+        }
+        .emit();
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_set_min_interval: done");
+        Ok(())
+    }
+
+    fn process_set_fee(accounts: &[AccountInfo], fee_lamports: u64) -> ProgramResult {
+        msg!("process_set_fee");
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let treasury_info = next_account_info(acc_iter)?;
+        let payer_info = next_account_info(acc_iter)?;
+        let rent_info = next_account_info(acc_iter)?;
+        let system_program_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        let mut settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        if settings.delay > 0 {
+            return Err(CounterError::TimelockRequired.into());
+        }
+        settings.treasury_bump = Self::create_treasury(
+            &settings,
+            treasury_info,
+            payer_info,
+            rent_info,
+            system_program_info,
+        )?;
+        let old = settings.values();
+        settings.fee_lamports = fee_lamports;
+        CounterEvent::SettingsUpdated {
+            settings: settings_info.key.to_bytes(),
+            old,
+            new: settings.values(),
+        }
+        .emit();
+
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_set_fee: done");
+        Ok(())
+    }
+
+    /// Create the treasury of the settings namespace unless it exists, and return its bump seed.
+    fn create_treasury<'a>(
+        settings: &Settings,
+        treasury_info: &AccountInfo<'a>,
+        payer_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+    ) -> Result<u8, ProgramError> {
+        let (treasury_pubkey, bump_seed) =
+            Settings::get_treasury_pubkey_with_bump(&settings.namespace);
+        if treasury_pubkey != *treasury_info.key {
+            return Err(CounterError::WrongTreasuryPDA.into());
+        }
+
+        if treasury_info.owner != &id() {
             let rent = &Rent::from_account_info(rent_info)?;
-            let lamports = rent.minimum_balance(space);
-            let signer_seeds: &[&[_]] = &[SETTINGS_SEED.as_bytes(), &[bump_seed]];
-            invoke_signed(
+            Self::create_pda_account(
+                payer_info,
+                treasury_info,
+                0,
+                rent,
+                system_program_info,
+                &[TREASURY_SEED.as_bytes(), &settings.namespace, &[bump_seed]],
+            )?;
+        }
+        Ok(bump_seed)
+    }
+
+    fn process_set_leaderboard_size(accounts: &[AccountInfo], size: u8) -> ProgramResult {
+        msg!("process_set_leaderboard_size: size={}", size);
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let leaderboard_info = next_account_info(acc_iter)?;
+        let payer_info = next_account_info(acc_iter)?;
+        let rent_info = next_account_info(acc_iter)?;
+        let system_program_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        let mut settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        if size as usize > MAX_LEADERBOARD_SIZE {
+            return Err(CounterError::InvalidLeaderboardSize.into());
+        }
+        let (leaderboard_pubkey, bump_seed) =
+            Leaderboard::get_leaderboard_pubkey_with_bump(&settings.namespace);
+        if leaderboard_pubkey != *leaderboard_info.key {
+            return Err(CounterError::WrongLeaderboardPDA.into());
+        }
+
+        let mut leaderboard = if leaderboard_info.owner != &id() {
+            let rent = &Rent::from_account_info(rent_info)?;
+            Self::create_pda_account(
+                payer_info,
+                leaderboard_info,
+                Leaderboard::LEN,
+                rent,
+                system_program_info,
+                &[LEADERBOARD_SEED.as_bytes(), &settings.namespace, &[bump_seed]],
+            )?;
+            Leaderboard::new(&settings.namespace)
+        } else {
+            load_account::<Leaderboard>(leaderboard_info)?
+        };
+        leaderboard.truncate(size as usize);
+        settings.leaderboard_size = size;
+        settings.leaderboard_bump = bump_seed;
+
+        let _ = leaderboard.serialize(&mut &mut leaderboard_info.data.borrow_mut()[..]);
+        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
+        msg!("process_set_leaderboard_size: done");
+        Ok(())
+    }
+
+    fn process_withdraw_treasury(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+        msg!("process_withdraw_treasury: amount={}", amount);
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let treasury_info = next_account_info(acc_iter)?;
+        let destination_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        let settings = Self::load_settings(settings_info)?;
+        Self::check_admin(&settings, admin_infos)?;
+        if !settings.is_ok_treasury_pubkey(treasury_info.key) || treasury_info.owner != &id() {
+            return Err(CounterError::WrongTreasuryPDA.into());
+        }
+
+        // the treasury stays rent exempt
+        let available = treasury_info
+            .lamports()
+            .saturating_sub(Rent::get()?.minimum_balance(treasury_info.data_len()));
+        if amount > available {
+            return Err(ProgramError::InsufficientFunds);
+        }
+        **treasury_info.lamports.borrow_mut() -= amount;
+        **destination_info.lamports.borrow_mut() =
+            destination_info.lamports().checked_add(amount).ok_or(ProgramError::InvalidArgument)?;
+
+        msg!("process_withdraw_treasury: done");
+        Ok(())
+    }
+
+    fn process_get_counter(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_get_counter");
+        let acc_iter = &mut accounts.iter();
+        let counter_info = next_account_info(acc_iter)?;
+
+        let counter = load_account::<Counter>(counter_info)?;
+        set_return_data(&counter.try_to_vec()?);
+
+        msg!("process_get_counter: done");
+        Ok(())
+    }
+
+    fn process_create_history(accounts: &[AccountInfo], capacity: u32) -> ProgramResult {
+        msg!("process_create_history: capacity={}", capacity);
+        let acc_iter = &mut accounts.iter();
+        let user_info = next_account_info(acc_iter)?;
+        let counter_info = next_account_info(acc_iter)?;
+        let history_info = next_account_info(acc_iter)?;
+        let rent_info = next_account_info(acc_iter)?;
+        let system_program_info = next_account_info(acc_iter)?;
+
+        // Checks
+        let mut counter = Self::load_owned_counter(user_info, counter_info)?;
+        if counter.has_history() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        let (history_pubkey, bump_seed) = History::get_history_pubkey_with_bump(counter_info.key);
+        if history_pubkey != *history_info.key {
+            return Err(CounterError::WrongHistoryPDA.into());
+        }
+        let history = History::new(counter_info.key, capacity)?;
+
+        let space = History::space(capacity);
+        let rent = &Rent::from_account_info(rent_info)?;
+        Self::create_pda_account(
+            user_info,
+            history_info,
+            space,
+            rent,
+            system_program_info,
+            &[HISTORY_SEED.as_bytes(), counter_info.key.as_ref(), &[bump_seed]],
+        )?;
+        counter.history = history_pubkey.to_bytes();
+
+        let _ = history.serialize(&mut &mut history_info.data.borrow_mut()[..History::LEN]);
+        let _ = counter.serialize(&mut &mut counter_info.data.borrow_mut()[..]);
+        msg!("process_create_history: done");
+        Ok(())
+    }
+
+    fn process_initialize_stats(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_initialize_stats");
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let stats_info = next_account_info(acc_iter)?;
+        let payer_info = next_account_info(acc_iter)?;
+        let rent_info = next_account_info(acc_iter)?;
+        let system_program_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        // Checks
+        let settings = Self::load_settings(settings_info)?;
+        if settings.namespace != DEFAULT_NAMESPACE {
+            return Err(CounterError::WrongSettingsPDA.into());
+        }
+        Self::check_admin(&settings, admin_infos)?;
+        let (stats_pubkey, bump_seed) = Stats::get_stats_pubkey_with_bump();
+        if stats_pubkey != *stats_info.key {
+            return Err(CounterError::WrongStatsPDA.into());
+        }
+        if !stats_info.data_is_empty() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        let stats = Stats::default();
+        let rent = &Rent::from_account_info(rent_info)?;
+        Self::create_pda_account(
+            payer_info,
+            stats_info,
+            Stats::LEN,
+            rent,
+            system_program_info,
+            &[STATS_SEED.as_bytes(), &[bump_seed]],
+        )?;
+
+        let _ = stats.serialize(&mut &mut stats_info.data.borrow_mut()[..]);
+        msg!("process_initialize_stats: done");
+        Ok(())
+    }
+
+    fn process_reset_stats(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_reset_stats");
+        let acc_iter = &mut accounts.iter();
+        let settings_info = next_account_info(acc_iter)?;
+        let stats_info = next_account_info(acc_iter)?;
+        let admin_infos = acc_iter.as_slice();
+
+        let settings = Self::load_settings(settings_info)?;
+        if settings.namespace != DEFAULT_NAMESPACE {
+            return Err(CounterError::WrongSettingsPDA.into());
+        }
+        Self::check_admin(&settings, admin_infos)?;
+        if *stats_info.key != Stats::get_stats_pubkey() {
+            return Err(CounterError::WrongStatsPDA.into());
+        }
+        let mut stats = load_account::<Stats>(stats_info)?;
+        stats.reset();
+
+        let _ = stats.serialize(&mut &mut stats_info.data.borrow_mut()[..]);
+        msg!("process_reset_stats: done");
+        Ok(())
+    }
+
+    /// Load a counter and check that the signer is its owner.
+    fn load_owned_counter(
+        user_info: &AccountInfo,
+        counter_info: &AccountInfo,
+    ) -> Result<Counter, ProgramError> {
+        if !user_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let counter = load_account::<Counter>(counter_info)?;
+        if !counter.is_ok_counter_pubkey(user_info.key, counter_info.key) {
+            return Err(CounterError::WrongCounterPDA.into());
+        }
+        Ok(counter)
+    }
+
+    /// Apply operations to a counter, loading and saving it once, and return it.
+    /// The signer is either the owner or the operator, which spends its allowance.
+    fn change_counter(accounts: &[AccountInfo], ops: &[CounterOp]) -> ProgramResult {
+        let acc_iter = &mut accounts.iter();
+        let user_info = next_account_info(acc_iter)?;
+        let counter_info = next_account_info(acc_iter)?;
+        let settings_info = next_account_info(acc_iter)?;
+
+        // Checks
+        if !user_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mut counter = load_account::<Counter>(counter_info)?;
+        if counter.is_ok_counter_pubkey(user_info.key, counter_info.key) {
+            counter.owner = user_info.key.to_bytes();
+        } else {
+            if !counter.is_operator(user_info.key) {
+                return Err(CounterError::WrongCounterPDA.into());
+            }
+            counter.use_allowance(ops.len() as u64)?;
+        }
+        let settings = Self::load_settings(settings_info)?;
+        if settings.namespace != counter.namespace {
+            return Err(CounterError::WrongSettingsPDA.into());
+        }
+        settings.check_not_paused()?;
+        let clock = Clock::get()?;
+        Self::use_rate_limit(&settings, &counter, user_info, accounts, clock.slot, ops.len())?;
+        counter.last_update_slot = clock.slot;
+
+        if settings.fee_lamports > 0 {
+            let treasury_info = next_account_info(acc_iter)?;
+            if !settings.is_ok_treasury_pubkey(treasury_info.key) {
+                return Err(CounterError::WrongTreasuryPDA.into());
+            }
+            let system_program_info = next_account_info(acc_iter)?;
+            if *system_program_info.key != system_program::id() {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            let fee = settings
+                .fee_lamports
+                .checked_mul(ops.len() as u64)
+                .ok_or(ProgramError::InvalidArgument)?;
+            invoke(
+                &system_instruction::transfer(user_info.key, treasury_info.key, fee),
+                &[user_info.clone(), treasury_info.clone(), system_program_info.clone()],
+            )?;
+        }
+        let history_info = Self::find_history(&counter, acc_iter.as_slice())?;
+        let mut history = match history_info {
+            Some(history_info) => Some(History::unpack_header(&history_info.data.borrow())?),
+            None => None,
+        };
+        let mut stats = Self::load_stats(counter.in_stats, acc_iter.as_slice())?;
+        let mut leaderboard = Self::load_leaderboard(&settings, acc_iter.as_slice())?;
+
+        for op in ops {
+            let delta = match *op {
+                CounterOp::Inc => settings.inc_step as i64,
+                CounterOp::Dec => -(settings.dec_step as i64),
+                CounterOp::Add { amount } => {
+                    settings.check_delta(amount)?;
+                    amount
+                }
+                CounterOp::Sub { amount } => {
+                    settings.check_delta(amount)?;
+                    -amount
+                }
+            };
+            let old_value = counter.value;
+            counter.apply_delta(delta, settings.saturating)?;
+            settings.check_bounds(counter.value)?;
+            if let Some((_, stats)) = &mut stats {
+                stats.record_change(&counter, old_value);
+            }
+            if let (Some(history), Some(history_info)) = (&mut history, history_info) {
+                let entry =
+                    HistoryEntry { delta, slot: clock.slot, signer: user_info.key.to_bytes() };
+                history.push(&mut history_info.data.borrow_mut(), &entry)?;
+            }
+
+            let (counter_key, amount, value) =
+                (counter_info.key.to_bytes(), delta.unsigned_abs(), counter.value);
+            match op {
+                CounterOp::Inc | CounterOp::Add { .. } => {
+                    CounterEvent::Incremented { counter: counter_key, amount, value }.emit()
+                }
+                CounterOp::Dec | CounterOp::Sub { .. } => {
+                    CounterEvent::Decremented { counter: counter_key, amount, value }.emit()
+                }
+            }
+        }
+
+        counter.ranked |= leaderboard.is_some();
+        let _ = counter.serialize(&mut &mut counter_info.data.borrow_mut()[..]);
+        if let Some((stats_info, stats)) = &stats {
+            let _ = stats.serialize(&mut &mut stats_info.data.borrow_mut()[..]);
+        }
+        if let Some((leaderboard_info, leaderboard)) = &mut leaderboard {
+            let entry = LeaderboardEntry {
+                counter: counter_info.key.to_bytes(),
+                owner: counter.owner,
+                value: counter.value,
+            };
+            leaderboard.update(entry, settings.leaderboard_size as usize);
+            let _ = leaderboard.serialize(&mut &mut leaderboard_info.data.borrow_mut()[..]);
+        }
+        set_return_data(&counter.try_to_vec()?);
+        Ok(())
+    }
+
+    /// Check the rate limit of the counter owner and record the change in its rate limit account.
+    /// The signer pays for the account on the first change of the owner.
+    fn use_rate_limit<'a>(
+        settings: &Settings,
+        counter: &Counter,
+        user_info: &AccountInfo<'a>,
+        account_infos: &[AccountInfo<'a>],
+        slot: u64,
+        ops: usize,
+    ) -> ProgramResult {
+        if settings.min_interval == 0 {
+            return Ok(());
+        }
+        let owner = Pubkey::new_from_array(counter.owner);
+        let (rate_limit_pubkey, bump_seed) =
+            RateLimit::get_rate_limit_pubkey_with_bump(&settings.namespace, &owner);
+        let rate_limit_info = account_infos
+            .iter()
+            .find(|info| *info.key == rate_limit_pubkey)
+            .ok_or(CounterError::RateLimitRequired)?;
+
+        let mut rate_limit = if Self::is_uninitialized(rate_limit_info) {
+            settings.check_rate_limit(None, slot, ops)?;
+            let system_program_info = account_infos
+                .iter()
+                .find(|info| *info.key == system_program::id())
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            Self::create_pda_account(
+                user_info,
+                rate_limit_info,
+                RateLimit::LEN,
+                &Rent::get()?,
+                system_program_info,
+                &[RATE_LIMIT_SEED.as_bytes(), &settings.namespace, owner.as_ref(), &[bump_seed]],
+            )?;
+            RateLimit::new(&settings.namespace, &owner)
+        } else {
+            let rate_limit = load_account::<RateLimit>(rate_limit_info)?;
+            settings.check_rate_limit(Some(rate_limit.last_update_slot), slot, ops)?;
+            rate_limit
+        };
+        rate_limit.last_update_slot = slot;
+
+        let _ = rate_limit.serialize(&mut &mut rate_limit_info.data.borrow_mut()[..]);
+        Ok(())
+    }
+
+    /// History account of a counter among the remaining accounts, if the counter has one.
+    fn find_history<'a, 'b>(
+        counter: &Counter,
+        account_infos: &'b [AccountInfo<'a>],
+    ) -> Result<Option<&'b AccountInfo<'a>>, ProgramError> {
+        if !counter.has_history() {
+            return Ok(None);
+        }
+        let history_info = account_infos
+            .iter()
+            .find(|info| info.key.to_bytes() == counter.history)
+            .ok_or(CounterError::HistoryRequired)?;
+        if history_info.owner != &id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        Ok(Some(history_info))
+    }
+
+    /// Stats account among the remaining accounts, `None` until it's initialized.
+    /// Only counters the stats don't count may leave it out, `required` is set for the others
+    /// and for new counters. An account at the stats address that isn't the program's is an error.
+    fn load_stats<'a, 'b>(
+        required: bool,
+        account_infos: &'b [AccountInfo<'a>],
+    ) -> Result<Option<(&'b AccountInfo<'a>, Stats)>, ProgramError> {
+        let stats_pubkey = Stats::get_stats_pubkey();
+        let stats_info = match account_infos.iter().find(|info| *info.key == stats_pubkey) {
+            Some(stats_info) => stats_info,
+            None if required => return Err(CounterError::StatsRequired.into()),
+            None => return Ok(None),
+        };
+        if Self::is_uninitialized(stats_info) {
+            return Ok(None);
+        }
+        Ok(Some((stats_info, load_account::<Stats>(stats_info)?)))
+    }
+
+    /// PDA of the program that wasn't created yet, it may have been pre-funded.
+    fn is_uninitialized(account_info: &AccountInfo) -> bool {
+        account_info.owner == &system_program::id() && account_info.data_is_empty()
+    }
+
+    /// Leaderboard account of the namespace among the remaining accounts, if it's enabled.
+    fn load_leaderboard<'a, 'b>(
+        settings: &Settings,
+        account_infos: &'b [AccountInfo<'a>],
+    ) -> Result<Option<(&'b AccountInfo<'a>, Leaderboard)>, ProgramError> {
+        if settings.leaderboard_size == 0 {
+            return Ok(None);
+        }
+        let leaderboard_info = account_infos
+            .iter()
+            .find(|info| settings.is_ok_leaderboard_pubkey(info.key))
+            .ok_or(CounterError::LeaderboardRequired)?;
+        Ok(Some((leaderboard_info, load_account::<Leaderboard>(leaderboard_info)?)))
+    }
+
+    /// Create a rent exempt PDA owned by the program. `create_account` fails on an address
+    /// someone already sent lamports to, such an account is topped up, allocated and assigned.
+    fn create_pda_account<'a>(
+        payer_info: &AccountInfo<'a>,
+        account_info: &AccountInfo<'a>,
+        space: usize,
+        rent: &Rent,
+        system_program_info: &AccountInfo<'a>,
+        signer_seeds: &[&[u8]],
+    ) -> ProgramResult {
+        let lamports = rent.minimum_balance(space);
+        if account_info.lamports() == 0 {
+            return invoke_signed(
                 &system_instruction::create_account(
-                    admin_info.key,
-                    &settings_pubkey,
+                    payer_info.key,
+                    account_info.key,
                     lamports,
                     space as u64,
                     &id(),
                 ),
-                &[admin_info.clone(), settings_info.clone(), system_program_info.clone()],
-                &[&signer_seeds],
+                &[payer_info.clone(), account_info.clone(), system_program_info.clone()],
+                &[signer_seeds],
+            );
+        }
+
+        let top_up = lamports.saturating_sub(account_info.lamports());
+        if top_up > 0 {
+            invoke(
+                &system_instruction::transfer(payer_info.key, account_info.key, top_up),
+                &[payer_info.clone(), account_info.clone(), system_program_info.clone()],
             )?;
         }
+        invoke_signed(
+            &system_instruction::allocate(account_info.key, space as u64),
+            &[account_info.clone(), system_program_info.clone()],
+            &[signer_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(account_info.key, &id()),
+            &[account_info.clone(), system_program_info.clone()],
+            &[signer_seeds],
+        )
+    }
 
-        let mut settings = Settings::try_from_slice(&settings_info.data.borrow())?;
-        if settings.admin != admin_info.key.to_bytes() && settings.admin != [0; 32] {
-            return Err(CounterError::AdminRequired.into());
+    /// Wipe an account of the program and send its lamports to a destination account.
+    fn close_account(account_info: &AccountInfo, destination_info: &AccountInfo) -> ProgramResult {
+        account_info.data.borrow_mut().fill(0);
+
+        let lamports = account_info.lamports();
+        **destination_info.lamports.borrow_mut() = destination_info
+            .lamports()
+            .checked_add(lamports)
+            .ok_or(ProgramError::InvalidArgument)?;
+        **account_info.lamports.borrow_mut() = 0;
+        Ok(())
+    }
+
+    /// Load a settings account and check that it's the PDA of its namespace.
+    fn load_settings(settings_info: &AccountInfo) -> Result<Settings, ProgramError> {
+        let settings = load_account::<Settings>(settings_info)?;
+        if !Settings::is_ok_settings_pubkey(&settings.namespace, settings_info.key) {
+            return Err(CounterError::WrongSettingsPDA.into());
         }
-        settings.admin = admin;
-        settings.inc_step = inc_step;
-        settings.dec_step = dec_step;
+        Ok(settings)
+    }
 
-        let _ = settings.serialize(&mut &mut settings_info.data.borrow_mut()[..]);
-        msg!("process_update_settings: done");
+    /// Check that at least `threshold` distinct admins from the set signed the transaction.
+    fn check_admin(settings: &Settings, admin_infos: &[AccountInfo]) -> ProgramResult {
+        let mut signers: Vec<&Pubkey> = Vec::new();
+        for admin_info in admin_infos {
+            if admin_info.is_signer
+                && settings.is_admin(admin_info.key)
+                && !signers.contains(&admin_info.key)
+            {
+                signers.push(admin_info.key);
+            }
+        }
+        if signers.is_empty() || signers.len() < settings.threshold as usize {
+            return Err(CounterError::AdminRequired.into());
+        }
         Ok(())
     }
+
+    /// Check that the signer is the upgrade authority stored in the program data account.
+    fn check_upgrade_authority(
+        program_data_info: &AccountInfo,
+        authority_info: &AccountInfo,
+    ) -> ProgramResult {
+        if *program_data_info.key != get_program_data_pubkey()
+            || program_data_info.owner != &bpf_loader_upgradeable::id()
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if !authority_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let metadata_len = UpgradeableLoaderState::size_of_programdata_metadata();
+        let state: UpgradeableLoaderState =
+            limited_deserialize(&program_data_info.data.borrow(), metadata_len as u64)
+                .map_err(|_| ProgramError::InvalidAccountData)?;
+        match state {
+            UpgradeableLoaderState::ProgramData {
+                upgrade_authority_address: Some(authority),
+                ..
+            } if authority == *authority_info.key => Ok(()),
+            _ => Err(CounterError::UpgradeAuthorityRequired.into()),
+        }
+    }
 }
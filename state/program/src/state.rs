@@ -1,55 +1,1120 @@
 use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::account_info::AccountInfo;
+use solana_program::program_error::ProgramError;
 use solana_program::pubkey::Pubkey;
 
-use crate::{id, COUNTER_SEED, SETTINGS_SEED};
+use crate::error::CounterError;
+use crate::{
+    id, COUNTER_SEED, DEFAULT_NAMESPACE, HISTORY_SEED, LEADERBOARD_SEED, RATE_LIMIT_SEED,
+    SETTINGS_SEED, STATS_SEED, TREASURY_SEED,
+};
 
-/// Each user has his own counter account.
+/// Layout version of the accounts created before `AccountHeader` was introduced.
+pub const LEGACY_VERSION: u8 = 1;
+
+/// Maximum number of admins in the admin set of `Settings`.
+pub const MAX_ADMINS: usize = 8;
+
+/// Maximum length of a counter label, it's limited by the address seed length.
+pub const MAX_LABEL_LEN: usize = 32;
+
+/// Maximum number of entries in a history, the account is created with CPI limited to 10KB.
+pub const MAX_HISTORY_CAPACITY: u32 = 200;
+
+/// Maximum number of counters on a leaderboard, `Settings::leaderboard_size` may be lower.
+pub const MAX_LEADERBOARD_SIZE: usize = 32;
+
+/// Every account of the program starts with this header.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub struct AccountHeader {
+    /// Type of the account
+    pub discriminator: [u8; 8],
+
+    /// Layout version of the account
+    pub version: u8,
+}
+
+impl AccountHeader {
+    pub const LEN: usize = 8 + 1;
+}
+
+/// Account types with a versioned layout.
+pub trait Versioned: BorshDeserialize {
+    const DISCRIMINATOR: [u8; 8];
+
+    /// Current layout version
+    const VERSION: u8;
+
+    /// Size of an account in the current layout
+    const LEN: usize;
+
+    /// Size of a legacy account without a header, `None` if the type always had a header
+    const LEGACY_LEN: Option<usize> = None;
+
+    fn header() -> AccountHeader {
+        AccountHeader { discriminator: Self::DISCRIMINATOR, version: Self::VERSION }
+    }
+
+    /// Deserialize account data. Accounts of an older layout have to be migrated first.
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if Self::version_of(data)? != Self::VERSION {
+            return Err(CounterError::OutdatedAccountVersion.into());
+        }
+        if data.len() != Self::LEN {
+            return Err(CounterError::InvalidAccountType.into());
+        }
+        Ok(Self::try_from_slice(data)?)
+    }
+
+    /// Layout version of raw account data. Legacy accounts are recognized by their size.
+    fn version_of(data: &[u8]) -> Result<u8, ProgramError> {
+        if Self::LEGACY_LEN == Some(data.len()) {
+            return Ok(LEGACY_VERSION);
+        }
+        let header = data.get(..AccountHeader::LEN).ok_or(CounterError::InvalidAccountType)?;
+        let header = AccountHeader::try_from_slice(header)?;
+        if header.discriminator != Self::DISCRIMINATOR {
+            return Err(CounterError::InvalidAccountType.into());
+        }
+        Ok(header.version)
+    }
+
+    /// Upgrade account data of an older layout to the current one, step by step.
+    fn migrate(data: &[u8]) -> Result<Vec<u8>, ProgramError> {
+        let mut version = Self::version_of(data)?;
+        let mut data = data.to_vec();
+        if Self::LEGACY_LEN == Some(data.len()) {
+            let header = AccountHeader { discriminator: Self::DISCRIMINATOR, version };
+            data.splice(..0, header.try_to_vec()?);
+        }
+        while version < Self::VERSION {
+            Self::migrate_step(version, &mut data)?;
+            version += 1;
+            data[8] = version;
+        }
+        Ok(data)
+    }
+
+    /// Upgrade account data from `version` to `version + 1`.
+    /// Legacy data already has a header prepended when this is called.
+    /// Types that never changed their layout have nothing to upgrade.
+    fn migrate_step(_version: u8, _data: &mut Vec<u8>) -> Result<(), ProgramError> {
+        Err(ProgramError::InvalidAccountData)
+    }
+}
+
+/// Deserialize an account owned by the program, checking its type, version and size.
+pub fn load_account<T: Versioned>(account_info: &AccountInfo) -> Result<T, ProgramError> {
+    if account_info.owner != &id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    T::unpack(&account_info.data.borrow())
+}
+
+/// Each user has his own counter accounts, one per label.
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub struct Counter {
+    pub header: AccountHeader,
+
     /// Increment this field every time by 1
     pub counter: u32,
 
     /// Value of a counter
     pub value: i64,
+
+    /// Namespace of the settings the counter uses
+    pub namespace: [u8; 32],
+
+    /// Label the counter address is derived from, padded with zeroes
+    pub label: [u8; MAX_LABEL_LEN],
+
+    /// Bump seed of the counter PDA. Unused by counters created with `create_with_seed`
+    pub bump: u8,
+
+    /// Delegate allowed to change the counter on behalf of its owner. Zeroes if there is none
+    pub operator: [u8; 32],
+
+    /// Number of operations the operator may still apply
+    pub allowance: u64,
+
+    /// Slot of the last change made by the owner or the operator. Zero if there was none
+    pub last_update_slot: u64,
+
+    /// Address of the history account of the counter. Zeroes if there is none
+    pub history: [u8; 32],
+
+    /// Owner of the counter. Zeroes for older counters until the owner changes them
+    pub owner: [u8; 32],
+
+    /// Counted by the stats since its creation, only such counters make up the stats
+    /// counter count and value sum
+    pub in_stats: bool,
+
+    /// Changed while its namespace had a leaderboard, closing it needs the leaderboard then
+    pub ranked: bool,
+}
+
+impl Versioned for Counter {
+    const DISCRIMINATOR: [u8; 8] = *b"counter\0";
+    const VERSION: u8 = 11;
+    const LEN: usize =
+        AccountHeader::LEN + 4 + 8 + 32 + MAX_LABEL_LEN + 1 + 32 + 8 + 8 + 32 + 32 + 1 + 1;
+    const LEGACY_LEN: Option<usize> = Some(4 + 8);
+
+    fn migrate_step(version: u8, data: &mut Vec<u8>) -> Result<(), ProgramError> {
+        match version {
+            // v2 only added the header
+            1 => Ok(()),
+            // v3 bound counters to a settings namespace
+            2 => {
+                data.extend_from_slice(&DEFAULT_NAMESPACE);
+                Ok(())
+            }
+            // v4 added labels, older counters were derived from `COUNTER_SEED`
+            3 => {
+                data.extend_from_slice(&Counter::pack_label(COUNTER_SEED)?);
+                Ok(())
+            }
+            // v5 added the PDA bump, older counters keep their `create_with_seed` address
+            4 => {
+                data.push(0);
+                Ok(())
+            }
+            // v6 added the operator, no one is approved
+            5 => {
+                data.extend_from_slice(&[0; 32 + 8]);
+                Ok(())
+            }
+            // v7 added the slot of the last change, older counters count as never changed
+            6 => {
+                data.extend_from_slice(&0_u64.to_le_bytes());
+                Ok(())
+            }
+            // v8 added the history address, older counters have no history
+            7 => {
+                data.extend_from_slice(&[0; 32]);
+                Ok(())
+            }
+            // v9 added the owner, older counters learn it from the next change by the owner
+            8 => {
+                data.extend_from_slice(&[0; 32]);
+                Ok(())
+            }
+            // v10 added the stats flag, older counters are left out of the stats counters
+            9 => {
+                data.push(false as u8);
+                Ok(())
+            }
+            // v11 added the leaderboard flag, older counters may already be on a leaderboard
+            10 => {
+                data.push(true as u8);
+                Ok(())
+            }
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Counter {
+            header: Self::header(),
+            counter: 0,
+            value: 0,
+            namespace: DEFAULT_NAMESPACE,
+            label: Self::pack_label(COUNTER_SEED).unwrap(),
+            bump: 0,
+            operator: [0; 32],
+            allowance: 0,
+            last_update_slot: 0,
+            history: [0; 32],
+            owner: [0; 32],
+            in_stats: false,
+            ranked: false,
+        }
+    }
 }
 
 impl Counter {
+    /// PDA seeds of a counter without a bump. The default counter has no label seed.
+    pub fn counter_seeds<'a>(user: &'a Pubkey, label: &'a str) -> Vec<&'a [u8]> {
+        if label == COUNTER_SEED {
+            vec![COUNTER_SEED.as_bytes(), user.as_ref()]
+        } else {
+            vec![COUNTER_SEED.as_bytes(), user.as_ref(), label.as_bytes()]
+        }
+    }
+
+    pub fn get_named_counter_pubkey_with_bump(user: &Pubkey, label: &str) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&Self::counter_seeds(user, label), &id())
+    }
+
+    pub fn get_named_counter_pubkey(user: &Pubkey, label: &str) -> Pubkey {
+        let (pubkey, _) = Self::get_named_counter_pubkey_with_bump(user, label);
+        pubkey
+    }
+
+    /// Address of the default counter of a user, labeled with `COUNTER_SEED`.
     pub fn get_counter_pubkey(user: &Pubkey) -> Pubkey {
-        Pubkey::create_with_seed(user, COUNTER_SEED, &id()).unwrap()
+        Self::get_named_counter_pubkey(user, COUNTER_SEED)
+    }
+
+    /// Address of a counter created with `create_with_seed` before counters became PDAs.
+    pub fn get_legacy_counter_pubkey(user: &Pubkey, label: &str) -> Pubkey {
+        Pubkey::create_with_seed(user, label, &id()).unwrap()
+    }
+
+    pub fn is_ok_legacy_counter_pubkey(user: &Pubkey, label: &str, counter: &Pubkey) -> bool {
+        match Pubkey::create_with_seed(user, label, &id()) {
+            Ok(pubkey) => pubkey.to_bytes() == counter.to_bytes(),
+            Err(_) => false,
+        }
+    }
+
+    /// Check the address of a loaded counter with its stored label and bump.
+    /// Counters created before PDAs are accepted at their `create_with_seed` address, they all
+    /// have the default label and were migrated without a bump.
+    pub fn is_ok_counter_pubkey(&self, user: &Pubkey, counter: &Pubkey) -> bool {
+        let label = match self.label() {
+            Ok(label) => label,
+            Err(_) => return false,
+        };
+        let bump = [self.bump];
+        let mut seeds = Self::counter_seeds(user, label);
+        seeds.push(&bump);
+        match Pubkey::create_program_address(&seeds, &id()) {
+            Ok(pubkey) if pubkey.to_bytes() == counter.to_bytes() => true,
+            _ => self.is_legacy() && Self::is_ok_legacy_counter_pubkey(user, label, counter),
+        }
+    }
+
+    /// Migrated from the layout before PDAs, only the default counter existed then.
+    fn is_legacy(&self) -> bool {
+        self.bump == 0 && matches!(self.label(), Ok(COUNTER_SEED))
     }
 
-    pub fn is_ok_counter_pubkey(user: &Pubkey, counter: &Pubkey) -> bool {
-        counter.to_bytes() == Self::get_counter_pubkey(user).to_bytes()
+    /// Zero padded label for the account data.
+    pub fn pack_label(label: &str) -> Result<[u8; MAX_LABEL_LEN], CounterError> {
+        if label.is_empty() || label.len() > MAX_LABEL_LEN || label.contains('\0') {
+            return Err(CounterError::InvalidLabel);
+        }
+        let mut packed = [0; MAX_LABEL_LEN];
+        packed[..label.len()].copy_from_slice(label.as_bytes());
+        Ok(packed)
+    }
+
+    pub fn label(&self) -> Result<&str, CounterError> {
+        let len = self.label.iter().position(|b| *b == 0).unwrap_or(MAX_LABEL_LEN);
+        std::str::from_utf8(&self.label[..len]).map_err(|_| CounterError::InvalidLabel)
+    }
+
+    /// Add `delta` to the value and count the operation.
+    /// On overflow either clamps both fields or fails, depending on `saturating`.
+    pub fn apply_delta(&mut self, delta: i64, saturating: bool) -> Result<(), CounterError> {
+        if saturating {
+            self.value = self.value.saturating_add(delta);
+            self.counter = self.counter.saturating_add(1);
+        } else {
+            let value = self.value.checked_add(delta).ok_or(CounterError::ValueOverflow)?;
+            let counter = self.counter.checked_add(1).ok_or(CounterError::OpCountOverflow)?;
+            self.value = value;
+            self.counter = counter;
+        }
+        Ok(())
+    }
+
+    pub fn is_operator(&self, key: &Pubkey) -> bool {
+        self.operator != [0; 32] && self.operator == key.to_bytes()
+    }
+
+    /// Spend `ops` operations of the operator allowance.
+    pub fn use_allowance(&mut self, ops: u64) -> Result<(), CounterError> {
+        self.allowance = self.allowance.checked_sub(ops).ok_or(CounterError::AllowanceExceeded)?;
+        Ok(())
+    }
+
+    /// Overwrite the value and count the operation.
+    pub fn set_value(&mut self, value: i64, saturating: bool) -> Result<(), CounterError> {
+        self.counter = if saturating {
+            self.counter.saturating_add(1)
+        } else {
+            self.counter.checked_add(1).ok_or(CounterError::OpCountOverflow)?
+        };
+        self.value = value;
+        Ok(())
+    }
+
+    pub fn has_history(&self) -> bool {
+        self.history != [0; 32]
     }
 }
 
-/// There is only one settings account. All counter accounts use it.
+/// Ring buffer with the last changes of a counter, created on demand by the counter owner.
+/// `capacity` entries of `HistoryEntry::LEN` bytes follow this header in the account data.
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct History {
+    pub header: AccountHeader,
+
+    /// Counter the history belongs to
+    pub counter: [u8; 32],
+
+    /// Number of entries the history keeps
+    pub capacity: u32,
+
+    /// Number of entries ever written, the next one goes to `total % capacity`
+    pub total: u64,
+}
+
+/// A single change of a counter.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub struct HistoryEntry {
+    /// Amount the value was changed by
+    pub delta: i64,
+
+    /// Slot of the change
+    pub slot: u64,
+
+    /// Owner, operator or admin who made the change
+    pub signer: [u8; 32],
+}
+
+impl HistoryEntry {
+    pub const LEN: usize = 8 + 8 + 32;
+}
+
+impl Versioned for History {
+    const DISCRIMINATOR: [u8; 8] = *b"history\0";
+    const VERSION: u8 = 1;
+    // size of the header only, see `History::unpack_header`
+    const LEN: usize = AccountHeader::LEN + 32 + 4 + 8;
+}
+
+impl History {
+    pub fn get_history_pubkey_with_bump(counter: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[HISTORY_SEED.as_bytes(), counter.as_ref()], &id())
+    }
+
+    pub fn get_history_pubkey(counter: &Pubkey) -> Pubkey {
+        let (pubkey, _) = Self::get_history_pubkey_with_bump(counter);
+        pubkey
+    }
+
+    /// Size of a history account holding `capacity` entries.
+    pub fn space(capacity: u32) -> usize {
+        Self::LEN + capacity as usize * HistoryEntry::LEN
+    }
+
+    pub fn new(counter: &Pubkey, capacity: u32) -> Result<Self, CounterError> {
+        if capacity == 0 || capacity > MAX_HISTORY_CAPACITY {
+            return Err(CounterError::InvalidHistoryCapacity);
+        }
+        Ok(History { header: Self::header(), counter: counter.to_bytes(), capacity, total: 0 })
+    }
+
+    /// Deserialize the header of history account data, checking the size of the entries.
+    pub fn unpack_header(data: &[u8]) -> Result<Self, ProgramError> {
+        let history = Self::unpack(data.get(..Self::LEN).ok_or(CounterError::InvalidAccountType)?)?;
+        if data.len() != Self::space(history.capacity) {
+            return Err(CounterError::InvalidAccountType.into());
+        }
+        Ok(history)
+    }
+
+    fn entry_offset(&self, index: u64) -> usize {
+        Self::LEN + (index % self.capacity as u64) as usize * HistoryEntry::LEN
+    }
+
+    /// Write an entry over the oldest one and update the header in `data`.
+    pub fn push(&mut self, data: &mut [u8], entry: &HistoryEntry) -> Result<(), ProgramError> {
+        let offset = self.entry_offset(self.total);
+        entry.serialize(&mut &mut data[offset..offset + HistoryEntry::LEN])?;
+        self.total = self.total.wrapping_add(1);
+        self.serialize(&mut &mut data[..Self::LEN])?;
+        Ok(())
+    }
+
+    /// Decode history account data, the entries are returned from the oldest to the newest.
+    pub fn decode(data: &[u8]) -> Result<(Self, Vec<HistoryEntry>), ProgramError> {
+        let history = Self::unpack_header(data)?;
+        let count = history.total.min(history.capacity as u64);
+        let mut entries = Vec::with_capacity(count as usize);
+        for index in history.total - count..history.total {
+            let offset = history.entry_offset(index);
+            entries.push(HistoryEntry::try_from_slice(&data[offset..offset + HistoryEntry::LEN])?);
+        }
+        Ok((history, entries))
+    }
+}
+
+/// Aggregate statistics of all counters, a single PDA `[STATS_SEED]` managed by the admins
+/// of the default namespace. Counters created before the stats account, migrated ones included,
+/// count only by their changes, and only if the stats account is passed along.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct Stats {
+    pub header: AccountHeader,
+
+    /// Number of counter changes since the last reset
+    pub total_ops: u64,
+
+    /// Number of existing counters created since the stats account
+    pub counters: u64,
+
+    /// Sum of the values of the counters created since the stats account
+    pub value_sum: i128,
+
+    /// Lowest value a counter had since the last reset, `i64::MAX` if there was none
+    pub min_value: i64,
+
+    /// Highest value a counter had since the last reset, `i64::MIN` if there was none
+    pub max_value: i64,
+}
+
+impl Versioned for Stats {
+    const DISCRIMINATOR: [u8; 8] = *b"stats\0\0\0";
+    const VERSION: u8 = 1;
+    const LEN: usize = AccountHeader::LEN + 8 + 8 + 16 + 8 + 8;
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            header: Self::header(),
+            total_ops: 0,
+            counters: 0,
+            value_sum: 0,
+            min_value: i64::MAX,
+            max_value: i64::MIN,
+        }
+    }
+}
+
+impl Stats {
+    pub fn get_stats_pubkey_with_bump() -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[STATS_SEED.as_bytes()], &id())
+    }
+
+    pub fn get_stats_pubkey() -> Pubkey {
+        let (pubkey, _) = Self::get_stats_pubkey_with_bump();
+        pubkey
+    }
+
+    fn record_value(&mut self, value: i64) {
+        self.min_value = self.min_value.min(value);
+        self.max_value = self.max_value.max(value);
+    }
+
+    /// Count a new counter and mark it as counted.
+    pub fn record_create(&mut self, counter: &mut Counter) {
+        counter.in_stats = true;
+        self.counters = self.counters.saturating_add(1);
+        self.value_sum = self.value_sum.saturating_add(counter.value as i128);
+        self.record_value(counter.value);
+    }
+
+    /// Remove a closed counter, if it was counted.
+    pub fn record_close(&mut self, counter: &Counter) {
+        if !counter.in_stats {
+            return;
+        }
+        self.counters = self.counters.saturating_sub(1);
+        self.value_sum = self.value_sum.saturating_sub(counter.value as i128);
+    }
+
+    /// Count an operation which changed the value of a counter from `old`.
+    pub fn record_change(&mut self, counter: &Counter, old: i64) {
+        self.total_ops = self.total_ops.saturating_add(1);
+        if counter.in_stats {
+            self.value_sum = self.value_sum.saturating_add(counter.value as i128 - old as i128);
+        }
+        self.record_value(counter.value);
+    }
+
+    /// Start counting operations and extremes over.
+    /// The counters and their sum describe the existing counters, they are kept.
+    pub fn reset(&mut self) {
+        *self = Stats { counters: self.counters, value_sum: self.value_sum, ..Stats::default() };
+    }
+}
+
+/// Owners with the highest counter values in a namespace, PDA `[LEADERBOARD_SEED, namespace]`.
+/// An owner has a single entry, the value of its counter changed last. Counters of older
+/// accounts without a known owner rank on their own.
+/// Entries only change along with the counters: an owner pushed off the board isn't re-admitted
+/// until one of its counters changes again and qualifies, the board isn't rebuilt from the
+/// counters. Closing the counter of an entry removes it.
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct Leaderboard {
+    pub header: AccountHeader,
+
+    /// Namespace of the counters on the board
+    pub namespace: [u8; 32],
+
+    /// Number of entries in use
+    pub count: u8,
+
+    /// Entries from the highest value, only the first `count` entries are used.
+    /// Of equal values the one reached first ranks higher
+    pub entries: [LeaderboardEntry; MAX_LEADERBOARD_SIZE],
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct LeaderboardEntry {
+    /// Counter of the owner changed last
+    pub counter: [u8; 32],
+
+    /// Owner of the counter, zeroes if it isn't known
+    pub owner: [u8; 32],
+
+    /// Value of the counter
+    pub value: i64,
+}
+
+impl LeaderboardEntry {
+    pub const LEN: usize = 32 + 32 + 8;
+
+    /// Entries of the same owner, or of the same counter if its owner isn't known, share a rank.
+    fn is_same_rank(&self, other: &LeaderboardEntry) -> bool {
+        self.counter == other.counter || (self.owner != [0; 32] && self.owner == other.owner)
+    }
+}
+
+impl Versioned for Leaderboard {
+    const DISCRIMINATOR: [u8; 8] = *b"leaderbd";
+    const VERSION: u8 = 1;
+    const LEN: usize = AccountHeader::LEN + 32 + 1 + LeaderboardEntry::LEN * MAX_LEADERBOARD_SIZE;
+}
+
+impl Leaderboard {
+    pub fn new(namespace: &[u8; 32]) -> Self {
+        Leaderboard {
+            header: Self::header(),
+            namespace: *namespace,
+            count: 0,
+            entries: [LeaderboardEntry::default(); MAX_LEADERBOARD_SIZE],
+        }
+    }
+
+    pub fn get_leaderboard_pubkey_with_bump(namespace: &[u8; 32]) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[LEADERBOARD_SEED.as_bytes(), namespace], &id())
+    }
+
+    pub fn get_leaderboard_pubkey(namespace: &[u8; 32]) -> Pubkey {
+        let (pubkey, _) = Self::get_leaderboard_pubkey_with_bump(namespace);
+        pubkey
+    }
+
+    pub fn entries(&self) -> &[LeaderboardEntry] {
+        &self.entries[..self.count as usize]
+    }
+
+    /// Replace the entry of the owner of a changed counter and move it to its rank, keeping at
+    /// most `size` entries. An entry ranking below them is dropped.
+    pub fn update(&mut self, entry: LeaderboardEntry, size: usize) {
+        let mut entries: Vec<LeaderboardEntry> =
+            self.entries().iter().filter(|e| !e.is_same_rank(&entry)).copied().collect();
+        let rank = entries.iter().position(|e| e.value < entry.value).unwrap_or(entries.len());
+        entries.insert(rank, entry);
+        self.set_entries(&entries, size);
+    }
+
+    /// Drop the entry of a closed counter, the entries below it move up.
+    pub fn remove(&mut self, counter: &[u8; 32]) {
+        let entries: Vec<LeaderboardEntry> =
+            self.entries().iter().filter(|e| e.counter != *counter).copied().collect();
+        self.set_entries(&entries, MAX_LEADERBOARD_SIZE);
+    }
+
+    /// Drop the lowest entries above `size`.
+    pub fn truncate(&mut self, size: usize) {
+        let entries = self.entries().to_vec();
+        self.set_entries(&entries, size);
+    }
+
+    fn set_entries(&mut self, entries: &[LeaderboardEntry], size: usize) {
+        let count = entries.len().min(size).min(MAX_LEADERBOARD_SIZE);
+        self.entries = [LeaderboardEntry::default(); MAX_LEADERBOARD_SIZE];
+        self.entries[..count].copy_from_slice(&entries[..count]);
+        self.count = count as u8;
+    }
+}
+
+/// Last change of the counters of an owner in a namespace, PDA
+/// `[RATE_LIMIT_SEED, namespace, owner]`. Created by the first change of the owner while the
+/// settings have a `min_interval`.
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct RateLimit {
+    pub header: AccountHeader,
+
+    /// Namespace of the counters
+    pub namespace: [u8; 32],
+
+    /// Owner of the counters
+    pub owner: [u8; 32],
+
+    /// Slot of the last change of any counter of the owner
+    pub last_update_slot: u64,
+}
+
+impl Versioned for RateLimit {
+    const DISCRIMINATOR: [u8; 8] = *b"ratelimt";
+    const VERSION: u8 = 1;
+    const LEN: usize = AccountHeader::LEN + 32 + 32 + 8;
+}
+
+impl RateLimit {
+    pub fn new(namespace: &[u8; 32], owner: &Pubkey) -> Self {
+        RateLimit {
+            header: Self::header(),
+            namespace: *namespace,
+            owner: owner.to_bytes(),
+            last_update_slot: 0,
+        }
+    }
+
+    pub fn get_rate_limit_pubkey_with_bump(namespace: &[u8; 32], owner: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[RATE_LIMIT_SEED.as_bytes(), namespace, owner.as_ref()],
+            &id(),
+        )
+    }
+
+    pub fn get_rate_limit_pubkey(namespace: &[u8; 32], owner: &Pubkey) -> Pubkey {
+        let (pubkey, _) = Self::get_rate_limit_pubkey_with_bump(namespace, owner);
+        pubkey
+    }
+}
+
+/// There is one settings account per namespace. Counters use the settings of their namespace.
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub struct Settings {
-    /// Only admin can change this account
-    pub admin: [u8; 32],
+    pub header: AccountHeader,
+
+    /// Admin set, only the first `admin_count` entries are used
+    pub admins: [[u8; 32]; MAX_ADMINS],
+
+    /// Number of admins in the set
+    pub admin_count: u8,
+
+    /// Number of admin signatures required to change this account
+    pub threshold: u8,
 
     /// Step value for increment
     pub inc_step: u32,
 
     /// Step value for decrement
     pub dec_step: u32,
+
+    /// Clamp counters on overflow instead of failing the instruction
+    pub saturating: bool,
+
+    /// Lowest value a counter may have
+    pub min_value: i64,
+
+    /// Highest value a counter may have
+    pub max_value: i64,
+
+    /// Proposed new admin, it joins the set once it accepts the role. Zeroes if there is no proposal
+    pub pending_admin: [u8; 32],
+
+    /// Seconds between queueing a settings change and executing it
+    pub delay: u32,
+
+    /// Queued settings change, valid only if `pending_eta` is set
+    pub pending_change: SettingsChange,
+
+    /// Unix timestamp after which the queued change can be executed. Zero if nothing is queued
+    pub pending_eta: i64,
+
+    /// Counters can't be changed while the program is paused
+    pub paused: bool,
+
+    /// Guardian can only pause the program. Zeroes if there is no guardian
+    pub guardian: [u8; 32],
+
+    /// Namespace the settings account is derived from
+    pub namespace: [u8; 32],
+
+    /// Largest amount a single `Add` or `Sub` may change a counter by
+    pub max_delta: u64,
+
+    /// Slots an owner waits between changes of its counters, per operation. Zero disables the limit
+    pub min_interval: u64,
+
+    /// Lamports the signer pays to the treasury for every counter operation
+    pub fee_lamports: u64,
+
+    /// Bump seed of the treasury PDA, set along with the fee
+    pub treasury_bump: u8,
+
+    /// Number of counters on the leaderboard of the namespace. Zero disables the leaderboard
+    pub leaderboard_size: u8,
+
+    /// Bump seed of the leaderboard PDA, set along with the size
+    pub leaderboard_bump: u8,
+}
+
+/// A settings change waiting for the timelock delay.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct SettingsChange {
+    pub inc_step: u32,
+    pub dec_step: u32,
+    pub saturating: bool,
+    pub min_value: i64,
+    pub max_value: i64,
+    pub delay: u32,
+    pub fee_lamports: u64,
+    pub max_delta: u64,
+    pub min_interval: u64,
+}
+
+impl SettingsChange {
+    pub const LEN: usize = 4 + 4 + 1 + 8 + 8 + 4 + 8 + 8 + 8;
+}
+
+impl Versioned for Settings {
+    const DISCRIMINATOR: [u8; 8] = *b"settings";
+    const VERSION: u8 = 12;
+    const LEN: usize = AccountHeader::LEN
+        + 32 * MAX_ADMINS
+        + 1
+        + 1
+        + 4
+        + 4
+        + 1
+        + 8
+        + 8
+        + 32
+        + 4
+        + SettingsChange::LEN
+        + 8
+        + 1
+        + 32
+        + 32
+        + 8
+        + 8
+        + 8
+        + 1
+        + 1
+        + 1;
+    const LEGACY_LEN: Option<usize> = Some(32 + 4 + 4);
+
+    fn migrate_step(version: u8, data: &mut Vec<u8>) -> Result<(), ProgramError> {
+        match version {
+            // v2 added the header, overflow mode and bounds
+            1 => {
+                data.push(false as u8);
+                data.extend_from_slice(&i64::MIN.to_le_bytes());
+                data.extend_from_slice(&i64::MAX.to_le_bytes());
+                Ok(())
+            }
+            // v3 added the pending admin
+            2 => {
+                data.extend_from_slice(&[0; 32]);
+                Ok(())
+            }
+            // v4 replaced the admin with an admin set, the old admin is its only member
+            3 => {
+                let offset = AccountHeader::LEN + 32;
+                let mut admin_set = vec![0; 32 * (MAX_ADMINS - 1)];
+                admin_set.extend_from_slice(&[1, 1]);
+                data.splice(offset..offset, admin_set);
+                Ok(())
+            }
+            // v5 added the timelock, no delay and nothing queued
+            4 => {
+                data.extend_from_slice(&[0; 4 + Self::V5_CHANGE_LEN + 8]);
+                Ok(())
+            }
+            // v6 added the pause switch and the guardian
+            5 => {
+                data.extend_from_slice(&[0; 1 + 32]);
+                Ok(())
+            }
+            // v7 added the namespace, older settings belong to the default one
+            6 => {
+                data.extend_from_slice(&DEFAULT_NAMESPACE);
+                Ok(())
+            }
+            // v8 added the max delta of arbitrary amounts, unlimited for older settings
+            7 => {
+                data.extend_from_slice(&u64::MAX.to_le_bytes());
+                Ok(())
+            }
+            // v9 added the rate limit, disabled for older settings
+            8 => {
+                data.extend_from_slice(&0_u64.to_le_bytes());
+                Ok(())
+            }
+            // v10 added the fee, older settings are free and have no treasury yet
+            9 => {
+                data.extend_from_slice(&[0; 8 + 1]);
+                Ok(())
+            }
+            // v11 added the leaderboard, disabled for older settings
+            10 => {
+                data.extend_from_slice(&[0; 1 + 1]);
+                Ok(())
+            }
+            // v12 added the fee, max delta and min interval to the queued change,
+            // a queued change keeps the current ones
+            11 => {
+                let change_end = Self::PENDING_CHANGE_OFFSET + Self::V5_CHANGE_LEN;
+                let pending_eta = &data[change_end..change_end + 8];
+                let kept = if pending_eta == [0; 8] {
+                    vec![0; 8 + 8 + 8]
+                } else {
+                    let max_delta = &data[change_end + Self::V11_MAX_DELTA_OFFSET..][..8];
+                    let min_interval = &data[change_end + Self::V11_MIN_INTERVAL_OFFSET..][..8];
+                    let fee = &data[change_end + Self::V11_FEE_OFFSET..][..8];
+                    [fee, max_delta, min_interval].concat()
+                };
+                data.splice(change_end..change_end, kept);
+                Ok(())
+            }
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            header: Self::header(),
+            admins: [[0; 32]; MAX_ADMINS],
+            admin_count: 0,
+            threshold: 0,
+            inc_step: 0,
+            dec_step: 0,
+            saturating: false,
+            min_value: i64::MIN,
+            max_value: i64::MAX,
+            pending_admin: [0; 32],
+            delay: 0,
+            pending_change: SettingsChange::default(),
+            pending_eta: 0,
+            paused: false,
+            guardian: [0; 32],
+            namespace: DEFAULT_NAMESPACE,
+            max_delta: u64::MAX,
+            min_interval: 0,
+            fee_lamports: 0,
+            treasury_bump: 0,
+            leaderboard_size: 0,
+            leaderboard_bump: 0,
+        }
+    }
 }
 
 impl Settings {
-    pub fn get_settings_pubkey_with_bump() -> (Pubkey, u8) {
-        Pubkey::find_program_address(&[SETTINGS_SEED.as_bytes()], &id())
+    /// Offset of `pending_change` in the account data
+    const PENDING_CHANGE_OFFSET: usize =
+        AccountHeader::LEN + 32 * MAX_ADMINS + 1 + 1 + 4 + 4 + 1 + 8 + 8 + 32 + 4;
+
+    /// Size of the queued change from v5 to v11, without the fee, max delta and min interval
+    const V5_CHANGE_LEN: usize = 4 + 4 + 1 + 8 + 8 + 4;
+
+    /// Offsets of v11 fields from the end of the queued change, past the pending eta,
+    /// the pause switch, the guardian and the namespace
+    const V11_MAX_DELTA_OFFSET: usize = 8 + 1 + 32 + 32;
+    const V11_MIN_INTERVAL_OFFSET: usize = Self::V11_MAX_DELTA_OFFSET + 8;
+    const V11_FEE_OFFSET: usize = Self::V11_MIN_INTERVAL_OFFSET + 8;
+
+    /// PDA seeds of the settings account without a bump.
+    /// The default namespace keeps the address of the settings created before namespaces.
+    pub fn settings_seeds(namespace: &[u8; 32]) -> Vec<&[u8]> {
+        if *namespace == DEFAULT_NAMESPACE {
+            vec![SETTINGS_SEED.as_bytes()]
+        } else {
+            vec![SETTINGS_SEED.as_bytes(), namespace]
+        }
     }
 
-    pub fn get_settings_pubkey() -> Pubkey {
-        let (pubkey, _) = Self::get_settings_pubkey_with_bump();
+    pub fn get_settings_pubkey_with_bump(namespace: &[u8; 32]) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&Self::settings_seeds(namespace), &id())
+    }
+
+    pub fn get_settings_pubkey(namespace: &[u8; 32]) -> Pubkey {
+        let (pubkey, _) = Self::get_settings_pubkey_with_bump(namespace);
         pubkey
     }
 
-    pub fn is_ok_settings_pubkey(settings_pubkey: &Pubkey) -> bool {
-        let (pubkey, _) = Self::get_settings_pubkey_with_bump();
+    pub fn is_ok_settings_pubkey(namespace: &[u8; 32], settings_pubkey: &Pubkey) -> bool {
+        let (pubkey, _) = Self::get_settings_pubkey_with_bump(namespace);
         pubkey.to_bytes() == settings_pubkey.to_bytes()
     }
+
+    /// The treasury collecting the fees of a namespace.
+    pub fn get_treasury_pubkey_with_bump(namespace: &[u8; 32]) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[TREASURY_SEED.as_bytes(), namespace], &id())
+    }
+
+    pub fn get_treasury_pubkey(namespace: &[u8; 32]) -> Pubkey {
+        let (pubkey, _) = Self::get_treasury_pubkey_with_bump(namespace);
+        pubkey
+    }
+
+    /// Check the treasury address with the stored bump.
+    pub fn is_ok_treasury_pubkey(&self, treasury_pubkey: &Pubkey) -> bool {
+        let seeds = [TREASURY_SEED.as_bytes(), &self.namespace, &[self.treasury_bump]];
+        match Pubkey::create_program_address(&seeds, &id()) {
+            Ok(pubkey) => pubkey.to_bytes() == treasury_pubkey.to_bytes(),
+            Err(_) => false,
+        }
+    }
+
+    /// Check the leaderboard address with the stored bump.
+    pub fn is_ok_leaderboard_pubkey(&self, leaderboard_pubkey: &Pubkey) -> bool {
+        let seeds = [LEADERBOARD_SEED.as_bytes(), &self.namespace, &[self.leaderboard_bump]];
+        match Pubkey::create_program_address(&seeds, &id()) {
+            Ok(pubkey) => pubkey.to_bytes() == leaderboard_pubkey.to_bytes(),
+            Err(_) => false,
+        }
+    }
+
+    pub fn check_not_paused(&self) -> Result<(), CounterError> {
+        if self.paused {
+            return Err(CounterError::ProgramPaused);
+        }
+        Ok(())
+    }
+
+    /// Check an `Add` or `Sub` amount against `max_delta`.
+    pub fn check_delta(&self, amount: i64) -> Result<(), CounterError> {
+        if amount <= 0 {
+            return Err(CounterError::InvalidAmount);
+        }
+        if amount as u64 > self.max_delta {
+            return Err(CounterError::DeltaTooLarge);
+        }
+        Ok(())
+    }
+
+    /// Check that `min_interval` slots per operation passed since the last change of a counter
+    /// of the same owner, so neither a batch nor another counter bypasses the limit.
+    /// `None` stands for an owner without a previous change, it may apply a single operation.
+    pub fn check_rate_limit(
+        &self,
+        last_update_slot: Option<u64>,
+        slot: u64,
+        ops: usize,
+    ) -> Result<(), CounterError> {
+        if self.min_interval == 0 {
+            return Ok(());
+        }
+        let last_update_slot = match last_update_slot {
+            Some(last_update_slot) => last_update_slot,
+            None if ops <= 1 => return Ok(()),
+            None => return Err(CounterError::RateLimited),
+        };
+        let wait = self.min_interval.saturating_mul(ops as u64);
+        if slot < last_update_slot.saturating_add(wait) {
+            return Err(CounterError::RateLimited);
+        }
+        Ok(())
+    }
+
+    pub fn is_guardian(&self, key: &Pubkey) -> bool {
+        self.guardian != [0; 32] && self.guardian == key.to_bytes()
+    }
+
+    pub fn check_bounds(&self, value: i64) -> Result<(), CounterError> {
+        if value < self.min_value || value > self.max_value {
+            return Err(CounterError::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    /// Current values of the fields a `SettingsChange` can change.
+    pub fn values(&self) -> SettingsChange {
+        SettingsChange {
+            inc_step: self.inc_step,
+            dec_step: self.dec_step,
+            saturating: self.saturating,
+            min_value: self.min_value,
+            max_value: self.max_value,
+            delay: self.delay,
+            fee_lamports: self.fee_lamports,
+            max_delta: self.max_delta,
+            min_interval: self.min_interval,
+        }
+    }
+
+    /// Queue a change to be executed `delay` seconds after `now`.
+    pub fn queue_change(&mut self, change: SettingsChange, now: i64) {
+        self.pending_change = change;
+        self.pending_eta = now + self.delay as i64;
+    }
+
+    /// Apply the queued change once its eta has passed.
+    pub fn execute_change(&mut self, now: i64) -> Result<(), CounterError> {
+        if self.pending_eta == 0 {
+            return Err(CounterError::NoPendingSettingsChange);
+        }
+        if now < self.pending_eta {
+            return Err(CounterError::TimelockNotExpired);
+        }
+        let change = self.pending_change;
+        self.inc_step = change.inc_step;
+        self.dec_step = change.dec_step;
+        self.saturating = change.saturating;
+        self.min_value = change.min_value;
+        self.max_value = change.max_value;
+        self.delay = change.delay;
+        self.fee_lamports = change.fee_lamports;
+        self.max_delta = change.max_delta;
+        self.min_interval = change.min_interval;
+        self.cancel_change();
+        Ok(())
+    }
+
+    pub fn cancel_change(&mut self) {
+        self.pending_change = SettingsChange::default();
+        self.pending_eta = 0;
+    }
+
+    pub fn admins(&self) -> &[[u8; 32]] {
+        &self.admins[..self.admin_count as usize]
+    }
+
+    pub fn is_admin(&self, key: &Pubkey) -> bool {
+        self.admins().contains(&key.to_bytes())
+    }
+
+    /// Add an admin to the set. Adding an existing admin changes nothing.
+    pub fn add_admin(&mut self, admin: [u8; 32]) -> Result<(), CounterError> {
+        if self.admins().contains(&admin) {
+            return Ok(());
+        }
+        let count = self.admin_count as usize;
+        if count == MAX_ADMINS {
+            return Err(CounterError::TooManyAdmins);
+        }
+        self.admins[count] = admin;
+        self.admin_count += 1;
+        Ok(())
+    }
+
+    /// Remove an admin from the set. The threshold has to stay reachable.
+    pub fn remove_admin(&mut self, admin: [u8; 32]) -> Result<(), CounterError> {
+        let index =
+            self.admins().iter().position(|a| *a == admin).ok_or(CounterError::UnknownAdmin)?;
+        if self.admin_count <= self.threshold {
+            return Err(CounterError::InvalidThreshold);
+        }
+        let last = self.admin_count as usize - 1;
+        self.admins[index] = self.admins[last];
+        self.admins[last] = [0; 32];
+        self.admin_count -= 1;
+        Ok(())
+    }
+
+    pub fn set_threshold(&mut self, threshold: u8) -> Result<(), CounterError> {
+        if threshold == 0 || threshold > self.admin_count {
+            return Err(CounterError::InvalidThreshold);
+        }
+        self.threshold = threshold;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -62,34 +1127,755 @@ mod test {
 
     #[test]
     fn test_serialization() {
-        let data = Settings { admin: [7_u8; 32], inc_step: 19, dec_step: 99 }.try_to_vec().unwrap();
+        let mut admins = [[0; 32]; MAX_ADMINS];
+        admins[0] = [7; 32];
+        let data = Settings {
+            header: Settings::header(),
+            admins,
+            admin_count: 1,
+            threshold: 1,
+            inc_step: 19,
+            dec_step: 99,
+            saturating: true,
+            min_value: -2,
+            max_value: 300,
+            pending_admin: [8_u8; 32],
+            delay: 60,
+            pending_change: SettingsChange::default(),
+            pending_eta: 1000,
+            paused: true,
+            guardian: [9_u8; 32],
+            namespace: [10_u8; 32],
+            max_delta: 500,
+            min_interval: 20,
+            fee_lamports: 5000,
+            treasury_bump: 253,
+            leaderboard_size: 10,
+            leaderboard_bump: 252,
+        }
+        .try_to_vec()
+        .unwrap();
         assert_eq!(
             data,
             [
-                7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
-                7, 7, 7, 7, 19, 0, 0, 0, 99, 0, 0, 0
+                &[115, 101, 116, 116, 105, 110, 103, 115, 12][..],
+                &[7; 32],
+                &[0; 32 * (MAX_ADMINS - 1)],
+                &[1, 1, 19, 0, 0, 0, 99, 0, 0, 0, 1],
+                &[254, 255, 255, 255, 255, 255, 255, 255, 44, 1, 0, 0, 0, 0, 0, 0],
+                &[8; 32],
+                &[60, 0, 0, 0],
+                &[0; SettingsChange::LEN],
+                &[232, 3, 0, 0, 0, 0, 0, 0],
+                &[1],
+                &[9; 32],
+                &[10; 32],
+                &[244, 1, 0, 0, 0, 0, 0, 0],
+                &[20, 0, 0, 0, 0, 0, 0, 0],
+                &[136, 19, 0, 0, 0, 0, 0, 0, 253],
+                &[10, 252],
             ]
+            .concat()
         );
+
+        let data = Counter {
+            counter: 1,
+            value: -1,
+            namespace: [10; 32],
+            bump: 254,
+            operator: [11; 32],
+            allowance: 3,
+            last_update_slot: 12,
+            history: [13; 32],
+            owner: [14; 32],
+            in_stats: true,
+            ranked: true,
+            ..Counter::default()
+        }
+        .try_to_vec()
+        .unwrap();
+        assert_eq!(
+            data,
+            [
+                &[99, 111, 117, 110, 116, 101, 114, 0, 11, 1, 0, 0, 0][..],
+                &[255, 255, 255, 255, 255, 255, 255, 255],
+                &[10; 32],
+                b"counter",
+                &[0; MAX_LABEL_LEN - 7],
+                &[254],
+                &[11; 32],
+                &[3, 0, 0, 0, 0, 0, 0, 0],
+                &[12, 0, 0, 0, 0, 0, 0, 0],
+                &[13; 32],
+                &[14; 32],
+                &[1],
+                &[1],
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_account_len() {
+        assert_eq!(Counter::default().try_to_vec().unwrap().len(), Counter::LEN);
+        assert_eq!(Settings::default().try_to_vec().unwrap().len(), Settings::LEN);
+        let history = History::new(&Pubkey::new_unique(), 1).unwrap();
+        assert_eq!(history.try_to_vec().unwrap().len(), History::LEN);
+        let entry = HistoryEntry { delta: 0, slot: 0, signer: [0; 32] };
+        assert_eq!(entry.try_to_vec().unwrap().len(), HistoryEntry::LEN);
+        assert_eq!(Stats::default().try_to_vec().unwrap().len(), Stats::LEN);
+        let leaderboard = Leaderboard::new(&DEFAULT_NAMESPACE);
+        assert_eq!(leaderboard.try_to_vec().unwrap().len(), Leaderboard::LEN);
+        let rate_limit = RateLimit::new(&DEFAULT_NAMESPACE, &Pubkey::new_unique());
+        assert_eq!(rate_limit.try_to_vec().unwrap().len(), RateLimit::LEN);
+    }
+
+    #[test]
+    fn test_load_account() {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = Counter { value: 7, ..Counter::default() }.try_to_vec().unwrap();
+        let owner = id();
+        let account_info =
+            AccountInfo::new(&key, false, true, &mut lamports, &mut data, &owner, false, 0);
+        assert_eq!(load_account::<Counter>(&account_info).unwrap().value, 7);
+    }
+
+    #[test]
+    fn test_load_account_wrong_owner() {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = Counter::default().try_to_vec().unwrap();
+        let owner = Pubkey::new_unique();
+        let account_info =
+            AccountInfo::new(&key, false, true, &mut lamports, &mut data, &owner, false, 0);
+        assert_eq!(
+            load_account::<Counter>(&account_info).unwrap_err(),
+            ProgramError::IncorrectProgramId
+        );
+    }
+
+    #[test]
+    fn test_load_account_wrong_type() {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = Counter::default().try_to_vec().unwrap();
+        let owner = id();
+        let account_info =
+            AccountInfo::new(&key, false, true, &mut lamports, &mut data, &owner, false, 0);
+        assert_eq!(
+            load_account::<Settings>(&account_info).unwrap_err(),
+            CounterError::InvalidAccountType.into()
+        );
+
+        // a counter with some extra data
+        let mut data = Counter::default().try_to_vec().unwrap();
+        data.push(0);
+        let account_info =
+            AccountInfo::new(&key, false, true, &mut lamports, &mut data, &owner, false, 0);
+        assert_eq!(
+            load_account::<Counter>(&account_info).unwrap_err(),
+            CounterError::InvalidAccountType.into()
+        );
+
+        // types without a legacy layout don't mistake an empty account for one
+        let mut data = vec![];
+        let account_info =
+            AccountInfo::new(&key, false, true, &mut lamports, &mut data, &owner, false, 0);
+        assert_eq!(
+            load_account::<Stats>(&account_info).unwrap_err(),
+            CounterError::InvalidAccountType.into()
+        );
+    }
+
+    #[test]
+    fn test_migrate_legacy_counter() {
+        let legacy = [5, 0, 0, 0, 253, 255, 255, 255, 255, 255, 255, 255];
+        assert!(matches!(
+            Counter::unpack(&legacy),
+            Err(ProgramError::Custom(code)) if code == CounterError::OutdatedAccountVersion as u32
+        ));
+
+        let data = Counter::migrate(&legacy).unwrap();
+        let counter = Counter::unpack(&data).unwrap();
+        assert_eq!(counter.header, Counter::header());
+        assert_eq!(counter.counter, 5);
+        assert_eq!(counter.value, -3);
+        assert_eq!(counter.namespace, DEFAULT_NAMESPACE);
+        assert_eq!(counter.label().unwrap(), COUNTER_SEED);
+        assert_eq!(counter.operator, [0; 32]);
+        assert_eq!(counter.allowance, 0);
+        assert_eq!(counter.last_update_slot, 0);
+        assert!(!counter.has_history());
+        assert_eq!(counter.owner, [0; 32]);
+        assert!(!counter.in_stats);
+        assert!(counter.ranked);
+
+        // migration of an up to date account changes nothing
+        assert_eq!(Counter::migrate(&data).unwrap(), data);
+    }
+
+    #[test]
+    fn test_migrate_legacy_settings() {
+        let legacy = [
+            7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+            7, 7, 7, 19, 0, 0, 0, 99, 0, 0, 0,
+        ];
+        assert!(Settings::unpack(&legacy).is_err());
+
+        let data = Settings::migrate(&legacy).unwrap();
+        let settings = Settings::unpack(&data).unwrap();
+        assert_eq!(settings.header, Settings::header());
+        assert_eq!(settings.admins(), [[7; 32]]);
+        assert_eq!(settings.threshold, 1);
+        assert_eq!(settings.inc_step, 19);
+        assert_eq!(settings.dec_step, 99);
+        assert!(!settings.saturating);
+        assert_eq!(settings.min_value, i64::MIN);
+        assert_eq!(settings.max_value, i64::MAX);
+        assert_eq!(settings.pending_admin, [0; 32]);
+        assert_eq!(settings.delay, 0);
+        assert_eq!(settings.pending_eta, 0);
+        assert!(!settings.paused);
+        assert_eq!(settings.guardian, [0; 32]);
+        assert_eq!(settings.namespace, DEFAULT_NAMESPACE);
+        assert_eq!(settings.max_delta, u64::MAX);
+        assert_eq!(settings.min_interval, 0);
+        assert_eq!(settings.fee_lamports, 0);
+        assert_eq!(settings.leaderboard_size, 0);
+    }
+
+    #[test]
+    fn test_migrate_queued_change() {
+        let settings = Settings {
+            pending_change: SettingsChange { inc_step: 3, ..SettingsChange::default() },
+            pending_eta: 1000,
+            max_delta: 50,
+            min_interval: 20,
+            fee_lamports: 5000,
+            ..Settings::default()
+        };
+        // v11 data, the queued change had no fee, max delta and min interval
+        let change_end = Settings::PENDING_CHANGE_OFFSET + Settings::V5_CHANGE_LEN;
+        let mut v11 = settings.try_to_vec().unwrap();
+        v11.drain(change_end..change_end + 8 + 8 + 8);
+        v11[8] = 11;
+
+        let data = Settings::migrate(&v11).unwrap();
+        let migrated = Settings::unpack(&data).unwrap();
+        assert_eq!(migrated.pending_change.inc_step, 3);
+        assert_eq!(migrated.pending_change.fee_lamports, 5000);
+        assert_eq!(migrated.pending_change.max_delta, 50);
+        assert_eq!(migrated.pending_change.min_interval, 20);
+        assert_eq!(migrated.pending_eta, 1000);
+        assert_eq!(migrated.fee_lamports, 5000);
+        assert_eq!(migrated.max_delta, 50);
+        assert_eq!(migrated.min_interval, 20);
+
+        // nothing queued, nothing to keep
+        let mut v11 = Settings { fee_lamports: 5000, ..Settings::default() }.try_to_vec().unwrap();
+        v11.drain(change_end..change_end + 8 + 8 + 8);
+        v11[8] = 11;
+        let migrated = Settings::unpack(&Settings::migrate(&v11).unwrap()).unwrap();
+        assert_eq!(migrated.pending_change, SettingsChange::default());
+        assert_eq!(migrated.fee_lamports, 5000);
+    }
+
+    #[test]
+    fn test_guardian() {
+        let zero = Pubkey::new_from_array([0; 32]);
+        let guardian = Pubkey::new_unique();
+        let settings = Settings::default();
+        assert!(!settings.is_guardian(&zero));
+
+        let settings = Settings { guardian: guardian.to_bytes(), paused: true, ..settings };
+        assert!(settings.is_guardian(&guardian));
+        assert!(!settings.is_guardian(&Pubkey::new_unique()));
+        assert!(matches!(settings.check_not_paused(), Err(CounterError::ProgramPaused)));
+    }
+
+    #[test]
+    fn test_check_bounds() {
+        let settings = Settings { min_value: 0, max_value: 10, ..Settings::default() };
+        assert!(settings.check_bounds(0).is_ok());
+        assert!(settings.check_bounds(10).is_ok());
+        assert!(matches!(settings.check_bounds(-1), Err(CounterError::OutOfBounds)));
+        assert!(matches!(settings.check_bounds(11), Err(CounterError::OutOfBounds)));
+    }
+
+    #[test]
+    fn test_check_delta() {
+        let settings = Settings { max_delta: 10, ..Settings::default() };
+        assert!(settings.check_delta(1).is_ok());
+        assert!(settings.check_delta(10).is_ok());
+        assert!(matches!(settings.check_delta(0), Err(CounterError::InvalidAmount)));
+        assert!(matches!(settings.check_delta(-1), Err(CounterError::InvalidAmount)));
+        assert!(matches!(settings.check_delta(11), Err(CounterError::DeltaTooLarge)));
+    }
+
+    #[test]
+    fn test_check_rate_limit() {
+        let settings = Settings::default();
+        assert!(settings.check_rate_limit(Some(10), 10, 1).is_ok());
+        assert!(settings.check_rate_limit(Some(10), 10, 2).is_ok());
+        assert!(settings.check_rate_limit(None, 10, 2).is_ok());
+
+        let settings = Settings { min_interval: 5, ..settings };
+        assert!(matches!(
+            settings.check_rate_limit(Some(10), 14, 1),
+            Err(CounterError::RateLimited)
+        ));
+        assert!(settings.check_rate_limit(Some(10), 15, 1).is_ok());
+        assert!(matches!(
+            settings.check_rate_limit(Some(10), 15, 2),
+            Err(CounterError::RateLimited)
+        ));
+        assert!(settings.check_rate_limit(Some(10), 20, 2).is_ok());
+
+        // a change at slot 0 is a change like any other
+        assert!(matches!(settings.check_rate_limit(Some(0), 1, 1), Err(CounterError::RateLimited)));
+
+        // the first change of an owner may apply a single operation
+        assert!(settings.check_rate_limit(None, 1, 1).is_ok());
+        assert!(matches!(settings.check_rate_limit(None, 1, 2), Err(CounterError::RateLimited)));
+    }
+
+    #[test]
+    fn test_settings_change_timelock() {
+        let mut settings = Settings { delay: 60, ..Settings::default() };
+        assert!(matches!(
+            settings.execute_change(1000),
+            Err(CounterError::NoPendingSettingsChange)
+        ));
+
+        let change = SettingsChange {
+            inc_step: 3,
+            dec_step: 4,
+            delay: 10,
+            fee_lamports: 5,
+            max_delta: 6,
+            min_interval: 7,
+            ..Default::default()
+        };
+        settings.queue_change(change, 1000);
+        assert_eq!(settings.pending_eta, 1060);
+        assert!(matches!(settings.execute_change(1059), Err(CounterError::TimelockNotExpired)));
+        assert_eq!(settings.inc_step, 0);
+
+        settings.execute_change(1060).unwrap();
+        assert_eq!(settings.inc_step, 3);
+        assert_eq!(settings.dec_step, 4);
+        assert_eq!(settings.delay, 10);
+        assert_eq!(settings.fee_lamports, 5);
+        assert_eq!(settings.max_delta, 6);
+        assert_eq!(settings.min_interval, 7);
+        assert_eq!(settings.pending_eta, 0);
+        assert_eq!(settings.pending_change, SettingsChange::default());
+    }
+
+    #[test]
+    fn test_add_admin() {
+        let mut settings = Settings::default();
+        for i in 0..MAX_ADMINS {
+            settings.add_admin([i as u8 + 1; 32]).unwrap();
+        }
+        assert_eq!(settings.admin_count as usize, MAX_ADMINS);
+        assert!(settings.is_admin(&Pubkey::new_from_array([1; 32])));
+
+        // an existing admin is not added twice
+        settings.add_admin([1; 32]).unwrap();
+        assert_eq!(settings.admin_count as usize, MAX_ADMINS);
+
+        assert!(matches!(settings.add_admin([99; 32]), Err(CounterError::TooManyAdmins)));
+    }
+
+    #[test]
+    fn test_remove_admin() {
+        let mut settings = Settings::default();
+        settings.add_admin([1; 32]).unwrap();
+        settings.add_admin([2; 32]).unwrap();
+        settings.add_admin([3; 32]).unwrap();
+        settings.set_threshold(2).unwrap();
+
+        assert!(matches!(settings.remove_admin([4; 32]), Err(CounterError::UnknownAdmin)));
+        settings.remove_admin([1; 32]).unwrap();
+        assert_eq!(settings.admins(), [[3; 32], [2; 32]]);
+        assert_eq!(settings.admins[2], [0; 32]);
+        assert!(!settings.is_admin(&Pubkey::new_from_array([1; 32])));
+
+        // the threshold would become unreachable
+        assert!(matches!(settings.remove_admin([2; 32]), Err(CounterError::InvalidThreshold)));
+    }
+
+    #[test]
+    fn test_set_threshold() {
+        let mut settings = Settings::default();
+        settings.add_admin([1; 32]).unwrap();
+        settings.add_admin([2; 32]).unwrap();
+        assert!(matches!(settings.set_threshold(0), Err(CounterError::InvalidThreshold)));
+        assert!(matches!(settings.set_threshold(3), Err(CounterError::InvalidThreshold)));
+        settings.set_threshold(2).unwrap();
+        assert_eq!(settings.threshold, 2);
+    }
+
+    #[test]
+    fn test_apply_delta() {
+        let mut counter = Counter::default();
+        counter.apply_delta(5, false).unwrap();
+        counter.apply_delta(-7, false).unwrap();
+        assert_eq!(counter.counter, 2);
+        assert_eq!(counter.value, -2);
+    }
+
+    #[test]
+    fn test_apply_delta_overflow() {
+        let mut counter = Counter { counter: 3, value: i64::MAX - 1, ..Counter::default() };
+        assert!(matches!(counter.apply_delta(2, false), Err(CounterError::ValueOverflow)));
+        assert_eq!(counter.counter, 3);
+        assert_eq!(counter.value, i64::MAX - 1);
+
+        let mut counter = Counter { counter: u32::MAX, value: 0, ..Counter::default() };
+        assert!(matches!(counter.apply_delta(1, false), Err(CounterError::OpCountOverflow)));
+        assert_eq!(counter.counter, u32::MAX);
+        assert_eq!(counter.value, 0);
+    }
+
+    #[test]
+    fn test_apply_delta_saturating() {
+        let mut counter = Counter { counter: u32::MAX, value: i64::MIN + 1, ..Counter::default() };
+        counter.apply_delta(-2, true).unwrap();
+        assert_eq!(counter.counter, u32::MAX);
+        assert_eq!(counter.value, i64::MIN);
+    }
+
+    #[test]
+    fn test_operator() {
+        let operator = Pubkey::new_unique();
+        let counter = Counter::default();
+        assert!(!counter.is_operator(&Pubkey::new_from_array([0; 32])));
+
+        let mut counter = Counter { operator: operator.to_bytes(), allowance: 2, ..counter };
+        assert!(counter.is_operator(&operator));
+        assert!(!counter.is_operator(&Pubkey::new_unique()));
+        assert!(matches!(counter.use_allowance(3), Err(CounterError::AllowanceExceeded)));
+        counter.use_allowance(2).unwrap();
+        assert_eq!(counter.allowance, 0);
+    }
+
+    #[test]
+    fn test_set_value() {
+        let mut counter = Counter { counter: 3, value: 8, ..Counter::default() };
+        counter.set_value(-5, false).unwrap();
+        assert_eq!(counter.counter, 4);
+        assert_eq!(counter.value, -5);
+
+        let mut counter = Counter { counter: u32::MAX, value: 0, ..Counter::default() };
+        assert!(matches!(counter.set_value(1, false), Err(CounterError::OpCountOverflow)));
+        counter.set_value(1, true).unwrap();
+        assert_eq!(counter.counter, u32::MAX);
+        assert_eq!(counter.value, 1);
+    }
+
+    #[test]
+    fn test_history() {
+        let counter = Pubkey::new_unique();
+        assert!(matches!(History::new(&counter, 0), Err(CounterError::InvalidHistoryCapacity)));
+        assert!(matches!(
+            History::new(&counter, MAX_HISTORY_CAPACITY + 1),
+            Err(CounterError::InvalidHistoryCapacity)
+        ));
+        assert!(History::space(MAX_HISTORY_CAPACITY) <= 10 * 1024);
+
+        let mut history = History::new(&counter, 3).unwrap();
+        let mut data = vec![0; History::space(3)];
+        history.serialize(&mut &mut data[..History::LEN]).unwrap();
+        assert_eq!(History::decode(&data).unwrap().1, []);
+
+        let entry = |delta| HistoryEntry { delta, slot: delta as u64, signer: [1; 32] };
+        for delta in 1..=5 {
+            history.push(&mut data, &entry(delta)).unwrap();
+        }
+        let (decoded, entries) = History::decode(&data).unwrap();
+        assert_eq!(decoded.counter, counter.to_bytes());
+        assert_eq!(decoded.total, 5);
+        assert_eq!(entries, [entry(3), entry(4), entry(5)]);
+
+        // entries of another capacity
+        assert!(History::decode(&data[..History::space(2)]).is_err());
+        assert!(History::decode(&Counter::default().try_to_vec().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut stats = Stats::default();
+        let (mut a, mut b) = (Counter::default(), Counter::default());
+        stats.record_create(&mut a);
+        stats.record_create(&mut b);
+        assert!(a.in_stats && b.in_stats);
+        a.value = 5;
+        stats.record_change(&a, 0);
+        b.value = -3;
+        stats.record_change(&b, 0);
+        a.value = 4;
+        stats.record_change(&a, 5);
+        assert_eq!(stats.total_ops, 3);
+        assert_eq!(stats.counters, 2);
+        assert_eq!(stats.value_sum, 1);
+        assert_eq!((stats.min_value, stats.max_value), (-3, 5));
+
+        stats.record_close(&a);
+        assert_eq!(stats.counters, 1);
+        assert_eq!(stats.value_sum, -3);
+
+        stats.reset();
+        assert_eq!(stats, Stats { counters: 1, value_sum: -3, ..Stats::default() });
+
+        // counters from before the stats only count by their changes
+        let older = Counter { value: 7, ..Counter::default() };
+        stats.record_change(&older, 6);
+        assert_eq!(stats.value_sum, -3);
+        assert_eq!((stats.total_ops, stats.max_value), (1, 7));
+        stats.record_close(&older);
+        assert_eq!(stats.counters, 1);
+        assert_eq!(stats.value_sum, -3);
+
+        // the sum of i64 values doesn't overflow
+        b.value = i64::MAX;
+        stats.record_change(&b, 0);
+        stats.record_change(&b, 0);
+        assert_eq!(stats.value_sum, 2 * i64::MAX as i128 - 3);
+        let mut stats = Stats { total_ops: u64::MAX, ..Stats::default() };
+        stats.record_change(&b, 0);
+        assert_eq!(stats.total_ops, u64::MAX);
+        stats.record_close(&b);
+        assert_eq!(stats.counters, 0);
+    }
+
+    fn entry(counter: u8, value: i64) -> LeaderboardEntry {
+        LeaderboardEntry { counter: [counter; 32], owner: [counter; 32], value }
+    }
+
+    #[test]
+    fn test_leaderboard_insert() {
+        let mut leaderboard = Leaderboard::new(&DEFAULT_NAMESPACE);
+        leaderboard.update(entry(1, 5), 3);
+        leaderboard.update(entry(2, 7), 3);
+        leaderboard.update(entry(3, -1), 3);
+        assert_eq!(leaderboard.entries(), [entry(2, 7), entry(1, 5), entry(3, -1)]);
+
+        // a counter on the board moves to its new rank
+        leaderboard.update(entry(3, 6), 3);
+        assert_eq!(leaderboard.entries(), [entry(2, 7), entry(3, 6), entry(1, 5)]);
+        leaderboard.update(entry(2, 0), 3);
+        assert_eq!(leaderboard.entries(), [entry(3, 6), entry(1, 5), entry(2, 0)]);
+    }
+
+    #[test]
+    fn test_leaderboard_evict() {
+        let mut leaderboard = Leaderboard::new(&DEFAULT_NAMESPACE);
+        leaderboard.update(entry(1, 5), 2);
+        leaderboard.update(entry(2, 7), 2);
+
+        // a counter below a full board doesn't qualify
+        leaderboard.update(entry(3, 4), 2);
+        assert_eq!(leaderboard.entries(), [entry(2, 7), entry(1, 5)]);
+
+        // a higher one evicts the lowest entry
+        leaderboard.update(entry(3, 6), 2);
+        assert_eq!(leaderboard.entries(), [entry(2, 7), entry(3, 6)]);
+
+        // a counter on the board stays there when it drops, until a higher one evicts it
+        leaderboard.update(entry(3, 1), 2);
+        assert_eq!(leaderboard.entries(), [entry(2, 7), entry(3, 1)]);
+        leaderboard.update(entry(1, 8), 2);
+        assert_eq!(leaderboard.entries(), [entry(1, 8), entry(2, 7)]);
+
+        leaderboard.truncate(1);
+        assert_eq!(leaderboard.entries(), [entry(1, 8)]);
+        assert_eq!(leaderboard.entries[1], LeaderboardEntry::default());
+    }
+
+    #[test]
+    fn test_leaderboard_remove() {
+        let mut leaderboard = Leaderboard::new(&DEFAULT_NAMESPACE);
+        leaderboard.update(entry(1, 5), 3);
+        leaderboard.update(entry(2, 7), 3);
+        leaderboard.update(entry(3, 6), 3);
+
+        leaderboard.remove(&[3; 32]);
+        assert_eq!(leaderboard.entries(), [entry(2, 7), entry(1, 5)]);
+        assert_eq!(leaderboard.entries[2], LeaderboardEntry::default());
+
+        // a counter off the board changes nothing
+        leaderboard.remove(&[4; 32]);
+        assert_eq!(leaderboard.entries(), [entry(2, 7), entry(1, 5)]);
+    }
+
+    #[test]
+    fn test_leaderboard_owners() {
+        let mut leaderboard = Leaderboard::new(&DEFAULT_NAMESPACE);
+        let counter = |counter: u8, owner: u8, value: i64| LeaderboardEntry {
+            counter: [counter; 32],
+            owner: [owner; 32],
+            value,
+        };
+        leaderboard.update(counter(1, 1, 5), 3);
+        leaderboard.update(counter(2, 2, 7), 3);
+
+        // an owner has a single entry, with its counter changed last
+        leaderboard.update(counter(3, 1, 6), 3);
+        assert_eq!(leaderboard.entries(), [counter(2, 2, 7), counter(3, 1, 6)]);
+        leaderboard.update(counter(1, 1, 1), 3);
+        assert_eq!(leaderboard.entries(), [counter(2, 2, 7), counter(1, 1, 1)]);
+
+        // closing a counter off the board keeps the entry of its owner
+        leaderboard.remove(&[3; 32]);
+        assert_eq!(leaderboard.entries(), [counter(2, 2, 7), counter(1, 1, 1)]);
+        leaderboard.remove(&[1; 32]);
+        assert_eq!(leaderboard.entries(), [counter(2, 2, 7)]);
+
+        // counters without a known owner rank on their own
+        leaderboard.update(counter(4, 0, 3), 3);
+        leaderboard.update(counter(5, 0, 4), 3);
+        assert_eq!(leaderboard.entries(), [counter(2, 2, 7), counter(5, 0, 4), counter(4, 0, 3)]);
+
+        // until their owner changes them
+        leaderboard.update(counter(4, 2, 8), 3);
+        assert_eq!(leaderboard.entries(), [counter(4, 2, 8), counter(5, 0, 4)]);
+    }
+
+    #[test]
+    fn test_leaderboard_ties() {
+        let mut leaderboard = Leaderboard::new(&DEFAULT_NAMESPACE);
+        leaderboard.update(entry(1, 5), 2);
+        leaderboard.update(entry(2, 5), 2);
+        assert_eq!(leaderboard.entries(), [entry(1, 5), entry(2, 5)]);
+
+        // a tie with the lowest entry of a full board doesn't qualify
+        leaderboard.update(entry(3, 5), 2);
+        assert_eq!(leaderboard.entries(), [entry(1, 5), entry(2, 5)]);
+
+        // reaching a value again ranks after the ones already there
+        leaderboard.update(entry(1, 6), 2);
+        leaderboard.update(entry(1, 5), 2);
+        assert_eq!(leaderboard.entries(), [entry(2, 5), entry(1, 5)]);
+    }
+
+    #[test]
+    fn test_get_leaderboard_pubkey() {
+        let (pubkey, bump) = Leaderboard::get_leaderboard_pubkey_with_bump(&DEFAULT_NAMESPACE);
+        let settings = Settings { leaderboard_bump: bump, ..Settings::default() };
+        assert!(settings.is_ok_leaderboard_pubkey(&pubkey));
+        assert!(!settings.is_ok_leaderboard_pubkey(&Leaderboard::get_leaderboard_pubkey(&[1; 32])));
     }
 
     #[test]
     fn test_get_settings_address_with_seed() {
-        let (address, bump) = Settings::get_settings_pubkey_with_bump();
+        let (address, bump) = Settings::get_settings_pubkey_with_bump(&DEFAULT_NAMESPACE);
         assert_eq!(
             address,
             Pubkey::from_str("4voA9ct4uAJuBVLNfoaPiU1VgpatMpGKRLHfvP8CZ147").unwrap()
         );
         assert_eq!(bump, 255);
+
+        let address = Settings::get_settings_pubkey(&[1; 32]);
+        assert_eq!(
+            address,
+            Pubkey::from_str("HmyjzW4ANsuVYWNCPNSLKisBdAF5Z6XmCfRqPWcTBvps").unwrap()
+        );
+        assert!(!Settings::is_ok_settings_pubkey(&DEFAULT_NAMESPACE, &address));
+    }
+
+    #[test]
+    fn test_get_treasury_pubkey() {
+        let (address, bump) = Settings::get_treasury_pubkey_with_bump(&DEFAULT_NAMESPACE);
+        assert_eq!(
+            address,
+            Pubkey::from_str("6r96ZDt5Bd31CzHvHx4oQE4vHoh7ydcHPN3qM74PzEB").unwrap()
+        );
+        assert_eq!(bump, 254);
+
+        let settings = Settings { treasury_bump: bump, ..Settings::default() };
+        assert!(settings.is_ok_treasury_pubkey(&address));
+        assert!(!settings.is_ok_treasury_pubkey(&Settings::get_treasury_pubkey(&[1; 32])));
+    }
+
+    #[test]
+    fn test_label() {
+        let counter =
+            Counter { label: Counter::pack_label("apples").unwrap(), ..Counter::default() };
+        assert_eq!(counter.label().unwrap(), "apples");
+
+        let label = "a".repeat(MAX_LABEL_LEN);
+        let counter = Counter { label: Counter::pack_label(&label).unwrap(), ..Counter::default() };
+        assert_eq!(counter.label().unwrap(), label);
+
+        assert!(matches!(Counter::pack_label(""), Err(CounterError::InvalidLabel)));
+        assert!(matches!(Counter::pack_label("a\0b"), Err(CounterError::InvalidLabel)));
+        assert!(matches!(
+            Counter::pack_label(&"a".repeat(MAX_LABEL_LEN + 1)),
+            Err(CounterError::InvalidLabel)
+        ));
+    }
+
+    #[test]
+    fn test_get_named_counter_pubkey() {
+        let user = Pubkey::new_unique();
+        let (pubkey, bump) = Counter::get_named_counter_pubkey_with_bump(&user, "apples");
+        let counter =
+            Counter { label: Counter::pack_label("apples").unwrap(), bump, ..Counter::default() };
+        assert!(counter.is_ok_counter_pubkey(&user, &pubkey));
+        assert!(!counter.is_ok_counter_pubkey(&Pubkey::new_unique(), &pubkey));
+
+        // another label or bump gives another address
+        let pears = Counter { label: Counter::pack_label("pears").unwrap(), ..counter };
+        assert!(!pears.is_ok_counter_pubkey(&user, &pubkey));
+        let wrong_bump = Counter { bump: bump.wrapping_sub(1), ..counter };
+        assert!(!wrong_bump.is_ok_counter_pubkey(&user, &pubkey));
+
+        assert_eq!(
+            Counter::get_named_counter_pubkey(&user, COUNTER_SEED),
+            Counter::get_counter_pubkey(&user)
+        );
     }
 
     #[test]
     fn test_get_counter_pubkey() {
-        let pubkey = Counter::get_counter_pubkey(
-            &Pubkey::from_str("FKr2pLkJXFpnJf2sUtStVwDiQPq61rKngtXyhLw8SQbF").unwrap(),
+        let user = Pubkey::from_str("FKr2pLkJXFpnJf2sUtStVwDiQPq61rKngtXyhLw8SQbF").unwrap();
+        let (pubkey, bump) = Counter::get_named_counter_pubkey_with_bump(&user, COUNTER_SEED);
+        assert_eq!(
+            pubkey,
+            Pubkey::from_str("GCSyG3ysX1B1pdeTWTEohrWDS1Cp7FG4SPyrwaURv2WY").unwrap()
         );
+        assert_eq!(bump, 254);
+    }
+
+    #[test]
+    fn test_legacy_counter_pubkey() {
+        let user = Pubkey::from_str("FKr2pLkJXFpnJf2sUtStVwDiQPq61rKngtXyhLw8SQbF").unwrap();
+        let pubkey = Counter::get_legacy_counter_pubkey(&user, COUNTER_SEED);
         assert_eq!(
             pubkey,
             Pubkey::from_str("9JVaomeo7Ps8D41whGLkz1c1wzWGfKpk62Mopnf3B274").unwrap()
         );
+
+        // migrated legacy counters keep their address
+        let counter = Counter::default();
+        assert!(counter.is_ok_counter_pubkey(&user, &pubkey));
+
+        // but only the default counter without a bump existed before PDAs
+        let (_, bump) = Counter::get_named_counter_pubkey_with_bump(&user, COUNTER_SEED);
+        let counter = Counter { bump, ..Counter::default() };
+        assert!(!counter.is_ok_counter_pubkey(&user, &pubkey));
+
+        let label = Counter::pack_label("apples").unwrap();
+        let pubkey = Counter::get_legacy_counter_pubkey(&user, "apples");
+        let counter = Counter { label, ..Counter::default() };
+        assert!(!counter.is_ok_counter_pubkey(&user, &pubkey));
+        let (_, bump) = Counter::get_named_counter_pubkey_with_bump(&user, "apples");
+        let counter = Counter { label, bump, ..Counter::default() };
+        assert!(!counter.is_ok_counter_pubkey(&user, &pubkey));
+        assert!(counter
+            .is_ok_counter_pubkey(&user, &Counter::get_named_counter_pubkey(&user, "apples")));
     }
 }
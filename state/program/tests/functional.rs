@@ -1,14 +1,35 @@
 #![cfg(feature = "test-bpf")]
 use borsh::{BorshDeserialize, BorshSerialize};
-use counter::{entrypoint::process_instruction, id, instruction::CounterInstruction};
+use counter::error::CounterError;
+use counter::event::CounterEvent;
+use counter::state::{
+    Counter, History, Leaderboard, LeaderboardEntry, RateLimit, Settings, SettingsChange, Stats,
+    Versioned, MAX_HISTORY_CAPACITY, MAX_LEADERBOARD_SIZE,
+};
 use counter::{
-    state::{Counter, Settings},
-    COUNTER_SEED,
+    cpi,
+    entrypoint::process_instruction,
+    get_program_data_pubkey, id,
+    instruction::{CounterInstruction, CounterOp, MAX_BATCH_OPS},
+    COUNTER_SEED, DEFAULT_NAMESPACE,
+};
+use solana_program::{
+    account_info::AccountInfo,
+    bpf_loader_upgradeable,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction,
 };
-use solana_program::system_instruction;
-use solana_program_test::{processor, tokio, ProgramTest, ProgramTestContext};
+use solana_program_test::{
+    processor, tokio, ProgramTest, ProgramTestBanksClientExt, ProgramTestContext,
+};
+use solana_sdk::account::Account;
 use solana_sdk::signature::{Keypair, Signer};
-use solana_sdk::transaction::Transaction;
+use solana_sdk::transaction::{Transaction, TransactionError};
 
 struct Env {
     ctx: ProgramTestContext,
@@ -16,14 +37,112 @@ struct Env {
     user: Keypair,
 }
 
+/// Program data account of the upgradeable loader with the given upgrade authority,
+/// serialized with bincode like `UpgradeableLoaderState::ProgramData`.
+fn program_data_account(upgrade_authority: &Pubkey) -> Account {
+    let mut data = vec![3, 0, 0, 0];
+    data.extend_from_slice(&0_u64.to_le_bytes());
+    data.push(1);
+    data.extend_from_slice(upgrade_authority.as_ref());
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: bpf_loader_upgradeable::id(),
+        ..Account::default()
+    }
+}
+
+/// Program calling the counter with CPI, it fails unless the counter has the expected value.
+/// Input is 0 to get the counter, 1 to increment or 2 to decrement it, followed by the expected
+/// value. Accounts are the ones of `Inc` followed by the counter program.
+fn caller_id() -> Pubkey {
+    Pubkey::new_from_array([42; 32])
+}
+
+fn process_caller(_program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
+    let expected = i64::from_le_bytes(input[1..9].try_into().unwrap());
+    let counter_accounts = cpi::CounterAccounts {
+        user: accounts[0].clone(),
+        counter: accounts[1].clone(),
+        settings: accounts[2].clone(),
+        fee: Some(cpi::FeeAccounts {
+            treasury: accounts[3].clone(),
+            system_program: accounts[4].clone(),
+        }),
+        history: Some(accounts[5].clone()),
+        stats: Some(accounts[6].clone()),
+        leaderboard: Some(accounts[7].clone()),
+        rate_limit: Some(cpi::RateLimitAccounts {
+            rate_limit: accounts[8].clone(),
+            system_program: accounts[4].clone(),
+        }),
+    };
+    let counter = match input[0] {
+        0 => cpi::get_counter(&accounts[1])?,
+        1 => cpi::inc(&counter_accounts, &[])?,
+        _ => cpi::dec(&counter_accounts, &[])?,
+    };
+    if counter.value != expected {
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok(())
+}
+
+fn caller_instruction(user: &Pubkey, op: u8, expected: i64) -> Instruction {
+    let mut accounts = CounterInstruction::inc(user, &DEFAULT_NAMESPACE).accounts;
+    accounts.push(AccountMeta::new_readonly(id(), false));
+    let mut data = vec![op];
+    data.extend_from_slice(&expected.to_le_bytes());
+    Instruction { program_id: caller_id(), accounts, data }
+}
+
 impl Env {
     async fn new() -> Self {
-        let program_test = ProgramTest::new("counter", id(), processor!(process_instruction));
-        let mut ctx = program_test.start_with_context().await;
+        let mut env = Self::new_without_settings().await;
+
+        // init settings account
+        let tx = Transaction::new_signed_with_payer(
+            &[CounterInstruction::initialize_settings(
+                &env.admin.pubkey(),
+                &DEFAULT_NAMESPACE,
+                1,
+                2,
+                false,
+                i64::MIN,
+                i64::MAX,
+            )],
+            Some(&env.admin.pubkey()),
+            &[&env.admin],
+            env.ctx.last_blockhash,
+        );
+        env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+        let acc = env
+            .ctx
+            .banks_client
+            .get_account(Settings::get_settings_pubkey(&DEFAULT_NAMESPACE))
+            .await
+            .unwrap()
+            .unwrap();
+        let settings = Settings::try_from_slice(acc.data.as_slice()).unwrap();
+        assert_eq!(settings.admins(), [env.admin.pubkey().to_bytes()]);
+        assert_eq!(settings.threshold, 1);
+        assert_eq!(settings.inc_step, 1);
+        assert_eq!(settings.dec_step, 2);
+
+        env
+    }
 
+    /// The admin is the upgrade authority of the program
+    async fn new_without_settings() -> Self {
         let admin = Keypair::new();
         let user = Keypair::new();
 
+        let mut program_test = ProgramTest::new("counter", id(), processor!(process_instruction));
+        program_test.add_program("caller", caller_id(), processor!(process_caller));
+        program_test.add_account(get_program_data_pubkey(), program_data_account(&admin.pubkey()));
+        let mut ctx = program_test.start_with_context().await;
+
         // credit admin and user accounts
         ctx.banks_client
             .process_transaction(Transaction::new_signed_with_payer(
@@ -46,41 +165,9 @@ impl Env {
             .await
             .unwrap();
 
-        // init settings account
-        let tx = Transaction::new_signed_with_payer(
-            &[CounterInstruction::update_settings(
-                &admin.pubkey(),
-                admin.pubkey().to_bytes(),
-                1,
-                2,
-            )],
-            Some(&admin.pubkey()),
-            &[&admin],
-            ctx.last_blockhash,
-        );
-        ctx.banks_client.process_transaction(tx).await.unwrap();
-
-        let acc =
-            ctx.banks_client.get_account(Settings::get_settings_pubkey()).await.unwrap().unwrap();
-        let settings = Settings::try_from_slice(acc.data.as_slice()).unwrap();
-        assert_eq!(settings.inc_step, 1);
-        assert_eq!(settings.dec_step, 2);
-
         // init counter account
-        let space = Counter { counter: 0, value: 0 }.try_to_vec().unwrap().len();
-        let rent = ctx.banks_client.get_rent().await.unwrap();
-        let lamports = rent.minimum_balance(space);
-        let ix = system_instruction::create_account_with_seed(
-            &user.pubkey(),
-            &Counter::get_counter_pubkey(&user.pubkey()),
-            &user.pubkey(),
-            COUNTER_SEED,
-            lamports,
-            space as u64,
-            &id(),
-        );
         let tx = Transaction::new_signed_with_payer(
-            &[ix],
+            &[CounterInstruction::create_counter(&user.pubkey(), &DEFAULT_NAMESPACE)],
             Some(&user.pubkey()),
             &[&user],
             ctx.last_blockhash,
@@ -91,12 +178,76 @@ impl Env {
     }
 }
 
+#[tokio::test]
+async fn test_create_counter() {
+    let mut env = Env::new().await;
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Counter::get_counter_pubkey(&env.user.pubkey()))
+        .await
+        .unwrap()
+        .unwrap();
+    let space = Counter::default().try_to_vec().unwrap().len();
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+    assert_eq!(acc.owner, id());
+    assert_eq!(acc.data.len(), space);
+    assert_eq!(acc.lamports, rent.minimum_balance(space));
+    let counter = Counter::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.counter, 0);
+    assert_eq!(counter.value, 0);
+
+    // a counter can't be created twice
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::create_counter(&env.user.pubkey(), &DEFAULT_NAMESPACE)],
+        Some(&env.ctx.payer.pubkey()),
+        &[&env.ctx.payer, &env.user],
+        env.ctx.last_blockhash,
+    );
+    assert!(env.ctx.banks_client.process_transaction(tx).await.is_err());
+}
+
+#[tokio::test]
+async fn test_create_prefunded_counter() {
+    let mut env = Env::new().await;
+    let counter_pubkey = Counter::get_named_counter_pubkey(&env.user.pubkey(), "apples");
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+
+    // lamports sent to the address beforehand don't block the creation
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::transfer(
+                &env.admin.pubkey(),
+                &counter_pubkey,
+                rent.minimum_balance(0),
+            ),
+            CounterInstruction::create_named_counter(
+                &env.user.pubkey(),
+                "apples",
+                &DEFAULT_NAMESPACE,
+            ),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().unwrap();
+    assert_eq!(acc.owner, id());
+    assert_eq!(acc.data.len(), Counter::LEN);
+    assert_eq!(acc.lamports, rent.minimum_balance(Counter::LEN));
+    let counter = Counter::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.owner, env.user.pubkey().to_bytes());
+}
+
 #[tokio::test]
 async fn test_inc() {
     let mut env = Env::new().await;
 
     let tx = Transaction::new_signed_with_payer(
-        &[CounterInstruction::inc(&env.user.pubkey())],
+        &[CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE)],
         Some(&env.user.pubkey()),
         &[&env.user],
         env.ctx.last_blockhash,
@@ -120,7 +271,7 @@ async fn test_dec() {
     let mut env = Env::new().await;
 
     let tx = Transaction::new_signed_with_payer(
-        &[CounterInstruction::dec(&env.user.pubkey())],
+        &[CounterInstruction::dec(&env.user.pubkey(), &DEFAULT_NAMESPACE)],
         Some(&env.user.pubkey()),
         &[&env.user],
         env.ctx.last_blockhash,
@@ -140,15 +291,182 @@ async fn test_dec() {
 }
 
 #[tokio::test]
-async fn test_update_settings() {
+async fn test_inc_above_max_value() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::update_settings(
+            &DEFAULT_NAMESPACE,
+            &[env.admin.pubkey()],
+            1,
+            2,
+            false,
+            0,
+            1,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // the upper bound itself is allowed
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE)],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    env.ctx.last_blockhash =
+        env.ctx.banks_client.get_new_latest_blockhash(&env.ctx.last_blockhash).await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE)],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::OutOfBounds as u32)
+        )
+    );
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Counter::get_counter_pubkey(&env.user.pubkey()))
+        .await
+        .unwrap()
+        .unwrap();
+    let counter = Counter::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.counter, 1);
+    assert_eq!(counter.value, 1);
+}
+
+#[tokio::test]
+async fn test_dec_below_min_value() {
     let mut env = Env::new().await;
 
     let tx = Transaction::new_signed_with_payer(
         &[CounterInstruction::update_settings(
+            &DEFAULT_NAMESPACE,
+            &[env.admin.pubkey()],
+            1,
+            2,
+            false,
+            -2,
+            10,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // the lower bound itself is allowed
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::dec(&env.user.pubkey(), &DEFAULT_NAMESPACE)],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    env.ctx.last_blockhash =
+        env.ctx.banks_client.get_new_latest_blockhash(&env.ctx.last_blockhash).await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::dec(&env.user.pubkey(), &DEFAULT_NAMESPACE)],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::OutOfBounds as u32)
+        )
+    );
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Counter::get_counter_pubkey(&env.user.pubkey()))
+        .await
+        .unwrap()
+        .unwrap();
+    let counter = Counter::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.counter, 1);
+    assert_eq!(counter.value, -2);
+}
+
+#[tokio::test]
+async fn test_close_counter() {
+    let mut env = Env::new().await;
+
+    let counter_pubkey = Counter::get_counter_pubkey(&env.user.pubkey());
+    let counter_lamports =
+        env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().unwrap().lamports;
+    let admin_lamports = env.ctx.banks_client.get_balance(env.admin.pubkey()).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::close_counter(
+            &env.user.pubkey(),
+            &DEFAULT_NAMESPACE,
             &env.admin.pubkey(),
-            *&env.admin.pubkey().to_bytes(),
+        )],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    assert!(env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().is_none());
+    assert_eq!(
+        env.ctx.banks_client.get_balance(env.admin.pubkey()).await.unwrap(),
+        admin_lamports + counter_lamports
+    );
+}
+
+#[tokio::test]
+async fn test_close_counter_requires_owner() {
+    let mut env = Env::new().await;
+
+    let mut ix = CounterInstruction::close_counter(
+        &env.user.pubkey(),
+        &DEFAULT_NAMESPACE,
+        &env.admin.pubkey(),
+    );
+    ix.accounts[0].pubkey = env.admin.pubkey();
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert!(env.ctx.banks_client.process_transaction(tx).await.is_err());
+
+    let counter_pubkey = Counter::get_counter_pubkey(&env.user.pubkey());
+    assert!(env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_update_settings() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::update_settings(
+            &DEFAULT_NAMESPACE,
+            &[env.admin.pubkey()],
             11,
             22,
+            true,
+            -100,
+            100,
         )],
         Some(&env.admin.pubkey()),
         &[&env.admin],
@@ -156,9 +474,2751 @@ async fn test_update_settings() {
     );
     env.ctx.banks_client.process_transaction(tx).await.unwrap();
 
-    let acc =
-        env.ctx.banks_client.get_account(Settings::get_settings_pubkey()).await.unwrap().unwrap();
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Settings::get_settings_pubkey(&DEFAULT_NAMESPACE))
+        .await
+        .unwrap()
+        .unwrap();
     let settings = Settings::try_from_slice(acc.data.as_slice()).unwrap();
     assert_eq!(settings.inc_step, 11);
     assert_eq!(settings.dec_step, 22);
+    assert!(settings.saturating);
+    assert_eq!(settings.min_value, -100);
+    assert_eq!(settings.max_value, 100);
+}
+
+#[tokio::test]
+async fn test_migrate_legacy_accounts() {
+    let admin = Keypair::new();
+    let user = Keypair::new();
+    let rent = Rent::default();
+
+    // accounts created by the first version of the program, without a header.
+    // A zero admin allowed anyone to update the settings
+    let mut program_test = ProgramTest::new("counter", id(), processor!(process_instruction));
+    program_test.add_account(get_program_data_pubkey(), program_data_account(&admin.pubkey()));
+    let legacy_settings = [[0; 32].as_slice(), &[1, 0, 0, 0, 2, 0, 0, 0]].concat();
+    program_test.add_account(
+        Settings::get_settings_pubkey(&DEFAULT_NAMESPACE),
+        Account {
+            lamports: rent.minimum_balance(legacy_settings.len()),
+            data: legacy_settings,
+            owner: id(),
+            ..Account::default()
+        },
+    );
+    let legacy_counter = vec![3, 0, 0, 0, 10, 0, 0, 0, 0, 0, 0, 0];
+    let counter_pubkey = Counter::get_legacy_counter_pubkey(&user.pubkey(), COUNTER_SEED);
+    program_test.add_account(
+        counter_pubkey,
+        Account {
+            lamports: rent.minimum_balance(legacy_counter.len()),
+            data: legacy_counter,
+            owner: id(),
+            ..Account::default()
+        },
+    );
+    program_test
+        .add_account(user.pubkey(), Account { lamports: 1_000_000_000, ..Account::default() });
+    let mut ctx = program_test.start_with_context().await;
+
+    // legacy counters keep their `create_with_seed` address
+    let mut inc_ix = CounterInstruction::inc(&user.pubkey(), &DEFAULT_NAMESPACE);
+    inc_ix.accounts[1].pubkey = counter_pubkey;
+
+    // legacy accounts can't be used until migrated
+    let tx = Transaction::new_signed_with_payer(
+        &[inc_ix.clone()],
+        Some(&user.pubkey()),
+        &[&user],
+        ctx.last_blockhash,
+    );
+    assert_eq!(
+        ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::OutdatedAccountVersion as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::migrate(
+                &user.pubkey(),
+                &Settings::get_settings_pubkey(&DEFAULT_NAMESPACE),
+            ),
+            CounterInstruction::migrate(&user.pubkey(), &counter_pubkey),
+            inc_ix,
+        ],
+        Some(&user.pubkey()),
+        &[&user],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = ctx
+        .banks_client
+        .get_account(Settings::get_settings_pubkey(&DEFAULT_NAMESPACE))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(acc.lamports, rent.minimum_balance(acc.data.len()));
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.admins(), [[0; 32]]);
+    assert_eq!(settings.inc_step, 1);
+    assert_eq!(settings.dec_step, 2);
+    assert_eq!(settings.min_value, i64::MIN);
+    assert_eq!(settings.max_value, i64::MAX);
+
+    // no one can sign for the zero admin, the upgrade authority reclaims the settings
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::reclaim_settings(&user.pubkey())],
+        Some(&user.pubkey()),
+        &[&user],
+        ctx.last_blockhash,
+    );
+    assert_eq!(
+        ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::UpgradeAuthorityRequired as u32)
+        )
+    );
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::reclaim_settings(&admin.pubkey()),
+            CounterInstruction::update_settings(
+                &DEFAULT_NAMESPACE,
+                &[admin.pubkey()],
+                3,
+                4,
+                false,
+                i64::MIN,
+                i64::MAX,
+            ),
+        ],
+        Some(&user.pubkey()),
+        &[&user, &admin],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = ctx
+        .banks_client
+        .get_account(Settings::get_settings_pubkey(&DEFAULT_NAMESPACE))
+        .await
+        .unwrap()
+        .unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.admins(), [admin.pubkey().to_bytes()]);
+    assert_eq!(settings.threshold, 1);
+    assert_eq!(settings.inc_step, 3);
+
+    // settings with a usable admin can't be reclaimed
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::reclaim_settings(&admin.pubkey())],
+        Some(&user.pubkey()),
+        &[&user, &admin],
+        ctx.last_blockhash,
+    );
+    assert_eq!(
+        ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::SettingsNotReclaimable as u32)
+        )
+    );
+
+    let acc = ctx.banks_client.get_account(counter_pubkey).await.unwrap().unwrap();
+    assert_eq!(acc.lamports, rent.minimum_balance(acc.data.len()));
+    let counter = Counter::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.counter, 4);
+    assert_eq!(counter.value, 11);
+
+    let mut close_ix =
+        CounterInstruction::close_counter(&user.pubkey(), &DEFAULT_NAMESPACE, &user.pubkey());
+    close_ix.accounts[1].pubkey = counter_pubkey;
+    let tx = Transaction::new_signed_with_payer(
+        &[close_ix],
+        Some(&user.pubkey()),
+        &[&user],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert!(ctx.banks_client.get_account(counter_pubkey).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_initialize_settings_requires_upgrade_authority() {
+    let mut env = Env::new_without_settings().await;
+
+    // a random user can't front-run the initialization
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::initialize_settings(
+            &env.user.pubkey(),
+            &DEFAULT_NAMESPACE,
+            1,
+            2,
+            false,
+            i64::MIN,
+            i64::MAX,
+        )],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::UpgradeAuthorityRequired as u32)
+        )
+    );
+
+    // nor create the settings account with UpdateSettings
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::update_settings(
+            &DEFAULT_NAMESPACE,
+            &[env.user.pubkey()],
+            1,
+            2,
+            false,
+            i64::MIN,
+            i64::MAX,
+        )],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert!(env.ctx.banks_client.process_transaction(tx).await.is_err());
+    assert!(env
+        .ctx
+        .banks_client
+        .get_account(Settings::get_settings_pubkey(&DEFAULT_NAMESPACE))
+        .await
+        .unwrap()
+        .is_none());
+
+    // the default namespace needs the program data account
+    let mut ix = CounterInstruction::initialize_settings(
+        &env.admin.pubkey(),
+        &DEFAULT_NAMESPACE,
+        1,
+        2,
+        false,
+        i64::MIN,
+        i64::MAX,
+    );
+    ix.accounts.pop();
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::NotEnoughAccountKeys)
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::initialize_settings(
+            &env.admin.pubkey(),
+            &DEFAULT_NAMESPACE,
+            1,
+            2,
+            false,
+            i64::MIN,
+            i64::MAX,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // settings can be initialized only once
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::initialize_settings(
+            &env.admin.pubkey(),
+            &DEFAULT_NAMESPACE,
+            3,
+            4,
+            false,
+            i64::MIN,
+            i64::MAX,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::AccountAlreadyInitialized)
+    );
+}
+
+/// Add a new admin to the set with `ProposeAdmin` and `AcceptAdmin`.
+async fn add_admin(ctx: &mut ProgramTestContext, admins: &[&Keypair], new_admin: &Keypair) {
+    let admin_pubkeys: Vec<Pubkey> = admins.iter().map(|admin| admin.pubkey()).collect();
+    let mut signers = admins.to_vec();
+    signers.push(new_admin);
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::propose_admin(
+                &DEFAULT_NAMESPACE,
+                &admin_pubkeys,
+                &new_admin.pubkey(),
+            ),
+            CounterInstruction::accept_admin(&DEFAULT_NAMESPACE, &new_admin.pubkey()),
+        ],
+        Some(&admins[0].pubkey()),
+        &signers,
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(tx).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_transfer_admin() {
+    let mut env = Env::new().await;
+    let new_admin = Keypair::new();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::propose_admin(
+            &DEFAULT_NAMESPACE,
+            &[env.admin.pubkey()],
+            &new_admin.pubkey(),
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Settings::get_settings_pubkey(&DEFAULT_NAMESPACE))
+        .await
+        .unwrap()
+        .unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.admins(), [env.admin.pubkey().to_bytes()]);
+    assert_eq!(settings.pending_admin, new_admin.pubkey().to_bytes());
+
+    // only the proposed admin can accept
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::accept_admin(&DEFAULT_NAMESPACE, &env.user.pubkey())],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::PendingAdminRequired as u32)
+        )
+    );
+
+    // the accepted admin joins the set and removes the previous one
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::accept_admin(&DEFAULT_NAMESPACE, &new_admin.pubkey()),
+            CounterInstruction::remove_admin(
+                &DEFAULT_NAMESPACE,
+                &[new_admin.pubkey()],
+                &env.admin.pubkey(),
+            ),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &new_admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Settings::get_settings_pubkey(&DEFAULT_NAMESPACE))
+        .await
+        .unwrap()
+        .unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.admins(), [new_admin.pubkey().to_bytes()]);
+    assert_eq!(settings.pending_admin, [0; 32]);
+
+    // the previous admin has no rights anymore
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::update_settings(
+            &DEFAULT_NAMESPACE,
+            &[env.admin.pubkey()],
+            5,
+            5,
+            false,
+            i64::MIN,
+            i64::MAX,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::AdminRequired as u32)
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_multisig_admin() {
+    let mut env = Env::new().await;
+    let admin = &env.admin;
+    let second_admin = Keypair::new();
+    let third_admin = Keypair::new();
+    add_admin(&mut env.ctx, &[admin], &second_admin).await;
+    add_admin(&mut env.ctx, &[admin], &third_admin).await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::set_threshold(&DEFAULT_NAMESPACE, &[admin.pubkey()], 2)],
+        Some(&admin.pubkey()),
+        &[admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // one admin and a signer outside of the set are not enough
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::update_settings(
+            &DEFAULT_NAMESPACE,
+            &[admin.pubkey(), env.user.pubkey()],
+            5,
+            6,
+            false,
+            i64::MIN,
+            i64::MAX,
+        )],
+        Some(&admin.pubkey()),
+        &[admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::AdminRequired as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::update_settings(
+            &DEFAULT_NAMESPACE,
+            &[admin.pubkey(), third_admin.pubkey()],
+            5,
+            6,
+            false,
+            i64::MIN,
+            i64::MAX,
+        )],
+        Some(&admin.pubkey()),
+        &[admin, &third_admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Settings::get_settings_pubkey(&DEFAULT_NAMESPACE))
+        .await
+        .unwrap()
+        .unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.inc_step, 5);
+    assert_eq!(settings.dec_step, 6);
+
+    // two admins remove the third one, the threshold stays at 2 of 2
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::remove_admin(
+            &DEFAULT_NAMESPACE,
+            &[second_admin.pubkey(), third_admin.pubkey()],
+            &admin.pubkey(),
+        )],
+        Some(&admin.pubkey()),
+        &[admin, &second_admin, &third_admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Settings::get_settings_pubkey(&DEFAULT_NAMESPACE))
+        .await
+        .unwrap()
+        .unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(
+        settings.admins(),
+        [third_admin.pubkey().to_bytes(), second_admin.pubkey().to_bytes()]
+    );
+    assert_eq!(settings.threshold, 2);
+
+    // removing another admin would make the threshold unreachable
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::remove_admin(
+            &DEFAULT_NAMESPACE,
+            &[second_admin.pubkey(), third_admin.pubkey()],
+            &third_admin.pubkey(),
+        )],
+        Some(&admin.pubkey()),
+        &[admin, &second_admin, &third_admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::InvalidThreshold as u32)
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_admin_proposal() {
+    let mut env = Env::new().await;
+    let new_admin = Keypair::new();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::propose_admin(
+                &DEFAULT_NAMESPACE,
+                &[env.admin.pubkey()],
+                &new_admin.pubkey(),
+            ),
+            CounterInstruction::cancel_admin_proposal(&DEFAULT_NAMESPACE, &[env.admin.pubkey()]),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::accept_admin(&DEFAULT_NAMESPACE, &new_admin.pubkey())],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &new_admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::PendingAdminRequired as u32)
+        )
+    );
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Settings::get_settings_pubkey(&DEFAULT_NAMESPACE))
+        .await
+        .unwrap()
+        .unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.admins(), [env.admin.pubkey().to_bytes()]);
+}
+
+#[tokio::test]
+async fn test_settings_timelock() {
+    let mut env = Env::new().await;
+
+    // without a delay the change can be executed right away
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::queue_settings_change(
+                &DEFAULT_NAMESPACE,
+                &env.admin.pubkey(),
+                &[env.admin.pubkey()],
+                SettingsChange {
+                    inc_step: 1,
+                    dec_step: 2,
+                    saturating: false,
+                    min_value: i64::MIN,
+                    max_value: i64::MAX,
+                    delay: 100,
+                    fee_lamports: 0,
+                    max_delta: u64::MAX,
+                    min_interval: 0,
+                },
+            ),
+            CounterInstruction::execute_settings_change(&DEFAULT_NAMESPACE),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // settings can't be changed immediately anymore
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::update_settings(
+            &DEFAULT_NAMESPACE,
+            &[env.admin.pubkey()],
+            5,
+            6,
+            false,
+            i64::MIN,
+            i64::MAX,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::TimelockRequired as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::queue_settings_change(
+            &DEFAULT_NAMESPACE,
+            &env.admin.pubkey(),
+            &[env.admin.pubkey()],
+            SettingsChange {
+                inc_step: 5,
+                dec_step: 6,
+                saturating: false,
+                min_value: i64::MIN,
+                max_value: i64::MAX,
+                delay: 100,
+                fee_lamports: 0,
+                max_delta: u64::MAX,
+                min_interval: 0,
+            },
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let clock = env.ctx.banks_client.get_sysvar::<Clock>().await.unwrap();
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Settings::get_settings_pubkey(&DEFAULT_NAMESPACE))
+        .await
+        .unwrap()
+        .unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.delay, 100);
+    assert_eq!(settings.pending_eta, clock.unix_timestamp + 100);
+
+    // anyone can execute, but only after the delay
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::execute_settings_change(&DEFAULT_NAMESPACE)],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::TimelockNotExpired as u32)
+        )
+    );
+
+    env.ctx.set_sysvar(&Clock { unix_timestamp: settings.pending_eta, ..clock });
+    env.ctx.last_blockhash =
+        env.ctx.banks_client.get_new_latest_blockhash(&env.ctx.last_blockhash).await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::execute_settings_change(&DEFAULT_NAMESPACE)],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Settings::get_settings_pubkey(&DEFAULT_NAMESPACE))
+        .await
+        .unwrap()
+        .unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.inc_step, 5);
+    assert_eq!(settings.dec_step, 6);
+    assert_eq!(settings.pending_eta, 0);
+}
+
+#[tokio::test]
+async fn test_cancel_settings_change() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::queue_settings_change(
+                &DEFAULT_NAMESPACE,
+                &env.admin.pubkey(),
+                &[env.admin.pubkey()],
+                SettingsChange {
+                    inc_step: 5,
+                    dec_step: 6,
+                    saturating: false,
+                    min_value: i64::MIN,
+                    max_value: i64::MAX,
+                    delay: 0,
+                    fee_lamports: 0,
+                    max_delta: u64::MAX,
+                    min_interval: 0,
+                },
+            ),
+            CounterInstruction::cancel_settings_change(&DEFAULT_NAMESPACE, &[env.admin.pubkey()]),
+            CounterInstruction::execute_settings_change(&DEFAULT_NAMESPACE),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            2,
+            InstructionError::Custom(CounterError::NoPendingSettingsChange as u32)
+        )
+    );
+
+    // only admins can cancel
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::queue_settings_change(
+                &DEFAULT_NAMESPACE,
+                &env.admin.pubkey(),
+                &[env.admin.pubkey()],
+                SettingsChange {
+                    inc_step: 5,
+                    dec_step: 6,
+                    saturating: false,
+                    min_value: i64::MIN,
+                    max_value: i64::MAX,
+                    delay: 0,
+                    fee_lamports: 0,
+                    max_delta: u64::MAX,
+                    min_interval: 0,
+                },
+            ),
+            CounterInstruction::cancel_settings_change(&DEFAULT_NAMESPACE, &[env.user.pubkey()]),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            1,
+            InstructionError::Custom(CounterError::AdminRequired as u32)
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_pause() {
+    let mut env = Env::new().await;
+    let guardian = Keypair::new();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::set_guardian(
+                &DEFAULT_NAMESPACE,
+                &[env.admin.pubkey()],
+                &guardian.pubkey(),
+            ),
+            CounterInstruction::pause(&DEFAULT_NAMESPACE, &[guardian.pubkey()]),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &guardian],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE)],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::ProgramPaused as u32)
+        )
+    );
+
+    // the guardian can't unpause or change settings
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::unpause(&DEFAULT_NAMESPACE, &[guardian.pubkey()])],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &guardian],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::AdminRequired as u32)
+        )
+    );
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::update_settings(
+            &DEFAULT_NAMESPACE,
+            &[guardian.pubkey()],
+            5,
+            6,
+            false,
+            i64::MIN,
+            i64::MAX,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &guardian],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::AdminRequired as u32)
+        )
+    );
+
+    env.ctx.last_blockhash =
+        env.ctx.banks_client.get_new_latest_blockhash(&env.ctx.last_blockhash).await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::unpause(&DEFAULT_NAMESPACE, &[env.admin.pubkey()]),
+            CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Counter::get_counter_pubkey(&env.user.pubkey()))
+        .await
+        .unwrap()
+        .unwrap();
+    let counter = Counter::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.value, 1);
+}
+
+#[tokio::test]
+async fn test_namespaces() {
+    let mut env = Env::new().await;
+    let namespace = [7; 32];
+    let other_user = Keypair::new();
+
+    // any user can take a free namespace and becomes its admin
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::initialize_settings(
+                &env.user.pubkey(),
+                &namespace,
+                10,
+                20,
+                false,
+                i64::MIN,
+                i64::MAX,
+            ),
+            system_instruction::transfer(&env.user.pubkey(), &other_user.pubkey(), 100_000_000),
+            CounterInstruction::create_counter(&other_user.pubkey(), &namespace),
+            CounterInstruction::inc(&other_user.pubkey(), &namespace),
+        ],
+        Some(&env.user.pubkey()),
+        &[&env.user, &other_user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Settings::get_settings_pubkey(&namespace))
+        .await
+        .unwrap()
+        .unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.namespace, namespace);
+    assert_eq!(settings.admins(), [env.user.pubkey().to_bytes()]);
+
+    // a namespace can be taken only once
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::initialize_settings(
+            &env.admin.pubkey(),
+            &namespace,
+            10,
+            20,
+            false,
+            i64::MIN,
+            i64::MAX,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::AccountAlreadyInitialized)
+    );
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Counter::get_counter_pubkey(&other_user.pubkey()))
+        .await
+        .unwrap()
+        .unwrap();
+    let counter = Counter::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.namespace, namespace);
+    assert_eq!(counter.value, 10);
+
+    // a counter can't use the settings of another namespace
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::inc(&other_user.pubkey(), &DEFAULT_NAMESPACE)],
+        Some(&other_user.pubkey()),
+        &[&other_user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::WrongSettingsPDA as u32)
+        )
+    );
+
+    // admins of the default namespace have no rights in other namespaces
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::update_settings(
+            &namespace,
+            &[env.admin.pubkey()],
+            5,
+            6,
+            false,
+            i64::MIN,
+            i64::MAX,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::AdminRequired as u32)
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_named_counters() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::create_named_counter(
+                &env.user.pubkey(),
+                "apples",
+                &DEFAULT_NAMESPACE,
+            ),
+            CounterInstruction::create_named_counter(
+                &env.user.pubkey(),
+                "pears",
+                &DEFAULT_NAMESPACE,
+            ),
+            CounterInstruction::inc_named(&env.user.pubkey(), "apples", &DEFAULT_NAMESPACE),
+            CounterInstruction::dec_named(&env.user.pubkey(), "pears", &DEFAULT_NAMESPACE),
+            CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE),
+            CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE),
+        ],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    for (label, value) in [("apples", 1), ("pears", -2), ("counter", 2)] {
+        let acc = env
+            .ctx
+            .banks_client
+            .get_account(Counter::get_named_counter_pubkey(&env.user.pubkey(), label))
+            .await
+            .unwrap()
+            .unwrap();
+        let counter = Counter::unpack(acc.data.as_slice()).unwrap();
+        assert_eq!(counter.label().unwrap(), label);
+        assert_eq!(counter.value, value);
+    }
+
+    // a counter of another user can't be used
+    let mut ix = CounterInstruction::inc_named(&env.admin.pubkey(), "apples", &DEFAULT_NAMESPACE);
+    ix.accounts[1].pubkey = Counter::get_named_counter_pubkey(&env.user.pubkey(), "apples");
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::WrongCounterPDA as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::close_named_counter(
+            &env.user.pubkey(),
+            "apples",
+            &DEFAULT_NAMESPACE,
+            &env.user.pubkey(),
+        )],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert!(env
+        .ctx
+        .banks_client
+        .get_account(Counter::get_named_counter_pubkey(&env.user.pubkey(), "apples"))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_add_sub() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::set_max_delta(&DEFAULT_NAMESPACE, &[env.admin.pubkey()], 100),
+            CounterInstruction::add(&env.user.pubkey(), &DEFAULT_NAMESPACE, 100),
+            CounterInstruction::sub(&env.user.pubkey(), &DEFAULT_NAMESPACE, 30),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Counter::get_counter_pubkey(&env.user.pubkey()))
+        .await
+        .unwrap()
+        .unwrap();
+    let counter = Counter::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.counter, 2);
+    assert_eq!(counter.value, 70);
+
+    // amounts above max_delta are rejected
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::add(&env.user.pubkey(), &DEFAULT_NAMESPACE, 101)],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::DeltaTooLarge as u32)
+        )
+    );
+
+    // amounts must be positive, `Sub` is the way to go down
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::add(&env.user.pubkey(), &DEFAULT_NAMESPACE, -1)],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::InvalidAmount as u32)
+        )
+    );
+
+    // only the admins can change max_delta
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::set_max_delta(&DEFAULT_NAMESPACE, &[env.user.pubkey()], 1000)],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::AdminRequired as u32)
+        )
+    );
+}
+
+/// Default compute budget of an instruction
+const DEFAULT_COMPUTE_UNIT_LIMIT: u64 = 200_000;
+
+/// A full batch with the fee, history, stats, leaderboard and rate limit enabled fits the default
+/// compute budget of an instruction.
+#[tokio::test]
+async fn test_batch_compute_budget() {
+    let mut env = Env::new().await;
+    let admin = env.admin.pubkey();
+    let user = env.user.pubkey();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::set_fee(&DEFAULT_NAMESPACE, &admin, &[admin], 10),
+            CounterInstruction::initialize_stats(&admin, &[admin]),
+            CounterInstruction::set_leaderboard_size(
+                &DEFAULT_NAMESPACE,
+                &admin,
+                &[admin],
+                MAX_LEADERBOARD_SIZE as u8,
+            ),
+            CounterInstruction::set_min_interval(&DEFAULT_NAMESPACE, &[admin], 1),
+        ],
+        Some(&admin),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    // the first change creates the rate limit account of the user
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::create_named_counter(&user, "apples", &DEFAULT_NAMESPACE),
+            CounterInstruction::create_history(&user, "apples", MAX_HISTORY_CAPACITY),
+            CounterInstruction::inc_named(&user, "apples", &DEFAULT_NAMESPACE),
+        ],
+        Some(&user),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let counter_pubkey = Counter::get_named_counter_pubkey(&user, "apples");
+    let acc = env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().unwrap();
+    let counter = Counter::unpack(acc.data.as_slice()).unwrap();
+    env.ctx.warp_to_slot(counter.last_update_slot + MAX_BATCH_OPS as u64).unwrap();
+
+    let ops = (0..MAX_BATCH_OPS)
+        .map(|i| if i % 2 == 0 { CounterOp::Add { amount: 3 } } else { CounterOp::Dec })
+        .collect();
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::batch_named(&user, "apples", &DEFAULT_NAMESPACE, ops)],
+        Some(&user),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    // compute units are only metered when the BPF program runs (`cargo test-bpf`)
+    if std::env::var("BPF_OUT_DIR").is_ok() || std::env::var("SBF_OUT_DIR").is_ok() {
+        let simulation = env.ctx.banks_client.simulate_transaction(tx.clone()).await.unwrap();
+        assert_eq!(simulation.result, Some(Ok(())));
+        let units = simulation.simulation_details.unwrap().units_consumed;
+        assert!(
+            units <= DEFAULT_COMPUTE_UNIT_LIMIT,
+            "a full batch consumed {} compute units",
+            units
+        );
+    }
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().unwrap();
+    let counter = Counter::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.value, MAX_BATCH_OPS as i64 / 2 + 1);
+    let history_pubkey = History::get_history_pubkey(&counter_pubkey);
+    let acc = env.ctx.banks_client.get_account(history_pubkey).await.unwrap().unwrap();
+    assert_eq!(History::decode(&acc.data).unwrap().0.total, MAX_BATCH_OPS as u64 + 1);
+    assert_eq!(get_stats(&mut env.ctx).await.value_sum, counter.value as i128);
+    assert_eq!(get_leaderboard(&mut env.ctx).await[0].value, counter.value);
+}
+
+#[tokio::test]
+async fn test_max_delta_timelock() {
+    let mut env = Env::new().await;
+    let settings_pubkey = Settings::get_settings_pubkey(&DEFAULT_NAMESPACE);
+    let admins = [env.admin.pubkey()];
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::queue_settings_change(
+                &DEFAULT_NAMESPACE,
+                &env.admin.pubkey(),
+                &admins,
+                SettingsChange {
+                    inc_step: 1,
+                    dec_step: 2,
+                    saturating: false,
+                    min_value: i64::MIN,
+                    max_value: i64::MAX,
+                    delay: 100,
+                    fee_lamports: 0,
+                    max_delta: u64::MAX,
+                    min_interval: 0,
+                },
+            ),
+            CounterInstruction::execute_settings_change(&DEFAULT_NAMESPACE),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::set_max_delta(&DEFAULT_NAMESPACE, &admins, 100)],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::TimelockRequired as u32)
+        )
+    );
+
+    let acc = env.ctx.banks_client.get_account(settings_pubkey).await.unwrap().unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::queue_settings_change(
+            &DEFAULT_NAMESPACE,
+            &env.admin.pubkey(),
+            &admins,
+            SettingsChange { max_delta: 100, ..settings.values() },
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env.ctx.banks_client.get_account(settings_pubkey).await.unwrap().unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.max_delta, u64::MAX);
+    assert_eq!(settings.pending_change, SettingsChange { max_delta: 100, ..settings.values() });
+
+    let clock = env.ctx.banks_client.get_sysvar::<Clock>().await.unwrap();
+    env.ctx.set_sysvar(&Clock { unix_timestamp: settings.pending_eta, ..clock });
+    env.ctx.last_blockhash =
+        env.ctx.banks_client.get_new_latest_blockhash(&env.ctx.last_blockhash).await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::execute_settings_change(&DEFAULT_NAMESPACE)],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env.ctx.banks_client.get_account(settings_pubkey).await.unwrap().unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.max_delta, 100);
+    assert_eq!(settings.pending_eta, 0);
+}
+
+#[tokio::test]
+async fn test_set() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::set(
+            &env.user.pubkey(),
+            &DEFAULT_NAMESPACE,
+            &[env.admin.pubkey()],
+            -42,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Counter::get_counter_pubkey(&env.user.pubkey()))
+        .await
+        .unwrap()
+        .unwrap();
+    let counter = Counter::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.counter, 1);
+    assert_eq!(counter.value, -42);
+
+    // the owner of a counter can't set it
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::set(
+            &env.user.pubkey(),
+            &DEFAULT_NAMESPACE,
+            &[env.user.pubkey()],
+            1000,
+        )],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::AdminRequired as u32)
+        )
+    );
+
+    // the value must stay within the bounds
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::update_settings(
+                &DEFAULT_NAMESPACE,
+                &[env.admin.pubkey()],
+                1,
+                2,
+                false,
+                0,
+                10,
+            ),
+            CounterInstruction::set(
+                &env.user.pubkey(),
+                &DEFAULT_NAMESPACE,
+                &[env.admin.pubkey()],
+                11,
+            ),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            1,
+            InstructionError::Custom(CounterError::OutOfBounds as u32)
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_batch() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::set_max_delta(&DEFAULT_NAMESPACE, &[env.admin.pubkey()], 10),
+            CounterInstruction::batch(
+                &env.user.pubkey(),
+                &DEFAULT_NAMESPACE,
+                vec![
+                    CounterOp::Inc,
+                    CounterOp::Inc,
+                    CounterOp::Add { amount: 5 },
+                    CounterOp::Dec,
+                    CounterOp::Sub { amount: 3 },
+                ],
+            ),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let counter_pubkey = Counter::get_counter_pubkey(&env.user.pubkey());
+    let acc = env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().unwrap();
+    let counter = Counter::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.counter, 5);
+    assert_eq!(counter.value, 2);
+
+    // a failing operation reverts the whole batch
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::batch(
+            &env.user.pubkey(),
+            &DEFAULT_NAMESPACE,
+            vec![CounterOp::Inc, CounterOp::Add { amount: 11 }],
+        )],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::DeltaTooLarge as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::batch(
+            &env.user.pubkey(),
+            &DEFAULT_NAMESPACE,
+            vec![CounterOp::Inc; MAX_BATCH_OPS + 1],
+        )],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::TooManyOps as u32)
+        )
+    );
+
+    // an empty batch would only reset the rate limit
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::batch(&env.user.pubkey(), &DEFAULT_NAMESPACE, vec![])],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InvalidInstructionData)
+    );
+
+    let acc = env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().unwrap();
+    let counter = Counter::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.counter, 5);
+    assert_eq!(counter.value, 2);
+}
+
+#[tokio::test]
+async fn test_operator() {
+    let mut env = Env::new().await;
+    let operator = Keypair::new();
+    let user = env.user.pubkey();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::approve_operator(&user, COUNTER_SEED, &operator.pubkey(), 2)],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // only the owner can approve an operator
+    let mut ix = CounterInstruction::approve_operator(&user, COUNTER_SEED, &operator.pubkey(), 9);
+    ix.accounts[0].pubkey = operator.pubkey();
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &operator],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::WrongCounterPDA as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::inc_by_operator(
+                &operator.pubkey(),
+                &user,
+                COUNTER_SEED,
+                &DEFAULT_NAMESPACE,
+            ),
+            CounterInstruction::dec_by_operator(
+                &operator.pubkey(),
+                &user,
+                COUNTER_SEED,
+                &DEFAULT_NAMESPACE,
+            ),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &operator],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let counter_pubkey = Counter::get_counter_pubkey(&user);
+    let acc = env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().unwrap();
+    let counter = Counter::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.counter, 2);
+    assert_eq!(counter.value, -1);
+    assert_eq!(counter.allowance, 0);
+
+    // the allowance is spent
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::inc_by_operator(
+            &operator.pubkey(),
+            &user,
+            COUNTER_SEED,
+            &DEFAULT_NAMESPACE,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &operator],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::AllowanceExceeded as u32)
+        )
+    );
+
+    // a revoked operator can't change the counter anymore
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::approve_operator(&user, COUNTER_SEED, &operator.pubkey(), 5),
+            CounterInstruction::revoke_operator(&user, COUNTER_SEED),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    env.ctx.last_blockhash =
+        env.ctx.banks_client.get_new_latest_blockhash(&env.ctx.last_blockhash).await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::inc_by_operator(
+            &operator.pubkey(),
+            &user,
+            COUNTER_SEED,
+            &DEFAULT_NAMESPACE,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &operator],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::WrongCounterPDA as u32)
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_rate_limit() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::create_named_counter(
+                &env.user.pubkey(),
+                "apples",
+                &DEFAULT_NAMESPACE,
+            ),
+            CounterInstruction::set_min_interval(&DEFAULT_NAMESPACE, &[env.admin.pubkey()], 100),
+            CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // the limit applies to the owner across all of its counters
+    for ix in [
+        CounterInstruction::dec(&env.user.pubkey(), &DEFAULT_NAMESPACE),
+        CounterInstruction::inc_named(&env.user.pubkey(), "apples", &DEFAULT_NAMESPACE),
+    ] {
+        let tx = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&env.user.pubkey()),
+            &[&env.user],
+            env.ctx.last_blockhash,
+        );
+        assert_eq!(
+            env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(CounterError::RateLimited as u32)
+            )
+        );
+    }
+
+    let rate_limit_pubkey =
+        RateLimit::get_rate_limit_pubkey(&DEFAULT_NAMESPACE, &env.user.pubkey());
+    let acc = env.ctx.banks_client.get_account(rate_limit_pubkey).await.unwrap().unwrap();
+    let rate_limit = RateLimit::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(rate_limit.owner, env.user.pubkey().to_bytes());
+
+    let counter_pubkey = Counter::get_counter_pubkey(&env.user.pubkey());
+    let acc = env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().unwrap();
+    let counter = Counter::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.value, 1);
+
+    env.ctx.warp_to_slot(counter.last_update_slot + 100).unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::dec(&env.user.pubkey(), &DEFAULT_NAMESPACE)],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().unwrap();
+    let counter = Counter::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.counter, 2);
+    assert_eq!(counter.value, -1);
+    assert!(counter.last_update_slot >= 100);
+
+    // a batch waits the interval once per operation
+    env.ctx.warp_to_slot(counter.last_update_slot + 100).unwrap();
+    let batch = CounterInstruction::batch(
+        &env.user.pubkey(),
+        &DEFAULT_NAMESPACE,
+        vec![CounterOp::Inc, CounterOp::Inc],
+    );
+    let tx = Transaction::new_signed_with_payer(
+        std::slice::from_ref(&batch),
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::RateLimited as u32)
+        )
+    );
+
+    env.ctx.warp_to_slot(counter.last_update_slot + 200).unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[batch],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().unwrap();
+    let counter = Counter::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.value, 1);
+    let acc = env.ctx.banks_client.get_account(rate_limit_pubkey).await.unwrap().unwrap();
+    let rate_limit = RateLimit::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(rate_limit.last_update_slot, counter.last_update_slot);
+
+    // the first change of an owner may only apply a single operation
+    let admin = env.admin.pubkey();
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::create_counter(&admin, &DEFAULT_NAMESPACE),
+            CounterInstruction::batch(
+                &admin,
+                &DEFAULT_NAMESPACE,
+                vec![CounterOp::Inc, CounterOp::Inc],
+            ),
+        ],
+        Some(&admin),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            1,
+            InstructionError::Custom(CounterError::RateLimited as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::create_counter(&admin, &DEFAULT_NAMESPACE),
+            CounterInstruction::inc(&admin, &DEFAULT_NAMESPACE),
+        ],
+        Some(&admin),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // without the rate limit account the change fails
+    let mut ix = CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE);
+    ix.accounts.pop();
+    env.ctx.warp_to_slot(counter.last_update_slot + 300).unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::RateLimitRequired as u32)
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_min_interval_timelock() {
+    let mut env = Env::new().await;
+    let settings_pubkey = Settings::get_settings_pubkey(&DEFAULT_NAMESPACE);
+    let admins = [env.admin.pubkey()];
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::queue_settings_change(
+                &DEFAULT_NAMESPACE,
+                &env.admin.pubkey(),
+                &admins,
+                SettingsChange {
+                    inc_step: 1,
+                    dec_step: 2,
+                    saturating: false,
+                    min_value: i64::MIN,
+                    max_value: i64::MAX,
+                    delay: 100,
+                    fee_lamports: 0,
+                    max_delta: u64::MAX,
+                    min_interval: 0,
+                },
+            ),
+            CounterInstruction::execute_settings_change(&DEFAULT_NAMESPACE),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::set_min_interval(&DEFAULT_NAMESPACE, &admins, 100)],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::TimelockRequired as u32)
+        )
+    );
+
+    let acc = env.ctx.banks_client.get_account(settings_pubkey).await.unwrap().unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::queue_settings_change(
+            &DEFAULT_NAMESPACE,
+            &env.admin.pubkey(),
+            &admins,
+            SettingsChange { min_interval: 100, ..settings.values() },
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env.ctx.banks_client.get_account(settings_pubkey).await.unwrap().unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.min_interval, 0);
+    assert_eq!(settings.pending_change, SettingsChange { min_interval: 100, ..settings.values() });
+
+    let clock = env.ctx.banks_client.get_sysvar::<Clock>().await.unwrap();
+    env.ctx.set_sysvar(&Clock { unix_timestamp: settings.pending_eta, ..clock });
+    env.ctx.last_blockhash =
+        env.ctx.banks_client.get_new_latest_blockhash(&env.ctx.last_blockhash).await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::execute_settings_change(&DEFAULT_NAMESPACE)],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env.ctx.banks_client.get_account(settings_pubkey).await.unwrap().unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.min_interval, 100);
+    assert_eq!(settings.pending_eta, 0);
+}
+
+#[tokio::test]
+async fn test_fee() {
+    let mut env = Env::new().await;
+    let treasury_pubkey = Settings::get_treasury_pubkey(&DEFAULT_NAMESPACE);
+    let destination = Pubkey::new_unique();
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::set_fee(
+            &DEFAULT_NAMESPACE,
+            &env.admin.pubkey(),
+            &[env.admin.pubkey()],
+            1000,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // the fee is paid through the system program
+    let mut ix = CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE);
+    ix.accounts[4].pubkey = Pubkey::new_unique();
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+
+    let user_balance = env.ctx.banks_client.get_balance(env.user.pubkey()).await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE),
+            CounterInstruction::batch(
+                &env.user.pubkey(),
+                &DEFAULT_NAMESPACE,
+                vec![CounterOp::Inc, CounterOp::Dec],
+            ),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // one fee per operation
+    assert_eq!(
+        env.ctx.banks_client.get_balance(env.user.pubkey()).await.unwrap(),
+        user_balance - 3000
+    );
+    let treasury = env.ctx.banks_client.get_account(treasury_pubkey).await.unwrap().unwrap();
+    assert_eq!(treasury.owner, id());
+    assert_eq!(treasury.lamports, rent.minimum_balance(0) + 3000);
+
+    // the treasury keeps its rent
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::withdraw_treasury(
+            &DEFAULT_NAMESPACE,
+            &[env.admin.pubkey()],
+            &destination,
+            3001,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InsufficientFunds)
+    );
+
+    // only the admins can withdraw
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::withdraw_treasury(
+            &DEFAULT_NAMESPACE,
+            &[env.user.pubkey()],
+            &destination,
+            3000,
+        )],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::AdminRequired as u32)
+        )
+    );
+
+    // the destination must hold at least the rent of an empty account
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::transfer(
+                &env.admin.pubkey(),
+                &destination,
+                rent.minimum_balance(0),
+            ),
+            CounterInstruction::withdraw_treasury(
+                &DEFAULT_NAMESPACE,
+                &[env.admin.pubkey()],
+                &destination,
+                3000,
+            ),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    assert_eq!(
+        env.ctx.banks_client.get_balance(destination).await.unwrap(),
+        rent.minimum_balance(0) + 3000
+    );
+    assert_eq!(
+        env.ctx.banks_client.get_balance(treasury_pubkey).await.unwrap(),
+        rent.minimum_balance(0)
+    );
+}
+
+#[tokio::test]
+async fn test_fee_timelock() {
+    let mut env = Env::new().await;
+    let treasury_pubkey = Settings::get_treasury_pubkey(&DEFAULT_NAMESPACE);
+    let settings_pubkey = Settings::get_settings_pubkey(&DEFAULT_NAMESPACE);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::queue_settings_change(
+                &DEFAULT_NAMESPACE,
+                &env.admin.pubkey(),
+                &[env.admin.pubkey()],
+                SettingsChange {
+                    inc_step: 1,
+                    dec_step: 1,
+                    saturating: false,
+                    min_value: i64::MIN,
+                    max_value: i64::MAX,
+                    delay: 100,
+                    fee_lamports: 0,
+                    max_delta: u64::MAX,
+                    min_interval: 0,
+                },
+            ),
+            CounterInstruction::execute_settings_change(&DEFAULT_NAMESPACE),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // the fee can't be raised under the feet of the users anymore
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::set_fee(
+            &DEFAULT_NAMESPACE,
+            &env.admin.pubkey(),
+            &[env.admin.pubkey()],
+            1000,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::TimelockRequired as u32)
+        )
+    );
+
+    let acc = env.ctx.banks_client.get_account(settings_pubkey).await.unwrap().unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::queue_settings_change(
+                &DEFAULT_NAMESPACE,
+                &env.admin.pubkey(),
+                &[env.admin.pubkey()],
+                SettingsChange { fee_lamports: 1000, ..settings.values() },
+            ),
+            CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env.ctx.banks_client.get_account(settings_pubkey).await.unwrap().unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.fee_lamports, 0);
+    assert_eq!(settings.pending_change, SettingsChange { fee_lamports: 1000, ..settings.values() });
+    let treasury = env.ctx.banks_client.get_account(treasury_pubkey).await.unwrap().unwrap();
+    assert_eq!(treasury.owner, id());
+
+    let clock = env.ctx.banks_client.get_sysvar::<Clock>().await.unwrap();
+    env.ctx.set_sysvar(&Clock { unix_timestamp: settings.pending_eta, ..clock });
+    env.ctx.last_blockhash =
+        env.ctx.banks_client.get_new_latest_blockhash(&env.ctx.last_blockhash).await.unwrap();
+    let user_balance = env.ctx.banks_client.get_balance(env.user.pubkey()).await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::execute_settings_change(&DEFAULT_NAMESPACE),
+            CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    assert_eq!(
+        env.ctx.banks_client.get_balance(env.user.pubkey()).await.unwrap(),
+        user_balance - 1000
+    );
+    let acc = env.ctx.banks_client.get_account(settings_pubkey).await.unwrap().unwrap();
+    let settings = Settings::unpack(acc.data.as_slice()).unwrap();
+    assert_eq!(settings.fee_lamports, 1000);
+    assert_eq!(settings.pending_eta, 0);
+}
+
+#[tokio::test]
+async fn test_events() {
+    let mut env = Env::new().await;
+    let counter = Counter::get_counter_pubkey(&env.user.pubkey()).to_bytes();
+    let settings = Settings::get_settings_pubkey(&DEFAULT_NAMESPACE).to_bytes();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::update_settings(
+                &DEFAULT_NAMESPACE,
+                &[env.admin.pubkey()],
+                5,
+                6,
+                false,
+                i64::MIN,
+                i64::MAX,
+            ),
+            CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE),
+            CounterInstruction::dec(&env.user.pubkey(), &DEFAULT_NAMESPACE),
+            CounterInstruction::set(
+                &env.user.pubkey(),
+                &DEFAULT_NAMESPACE,
+                &[env.admin.pubkey()],
+                7,
+            ),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    let simulation = env.ctx.banks_client.simulate_transaction(tx).await.unwrap();
+    assert_eq!(simulation.result, Some(Ok(())));
+    let logs = simulation.simulation_details.unwrap().logs;
+    // The native processor of solana-program-test doesn't log `sol_log_data`,
+    // events are only in the logs when the BPF program runs (`cargo test-bpf`)
+    if std::env::var("BPF_OUT_DIR").is_err() && std::env::var("SBF_OUT_DIR").is_err() {
+        return;
+    }
+
+    let old = SettingsChange {
+        inc_step: 1,
+        dec_step: 2,
+        saturating: false,
+        min_value: i64::MIN,
+        max_value: i64::MAX,
+        delay: 0,
+        fee_lamports: 0,
+        max_delta: u64::MAX,
+        min_interval: 0,
+    };
+    assert_eq!(
+        CounterEvent::from_logs(&logs),
+        [
+            CounterEvent::SettingsUpdated {
+                settings,
+                old,
+                new: SettingsChange { inc_step: 5, dec_step: 6, ..old },
+            },
+            CounterEvent::Incremented { counter, amount: 5, value: 5 },
+            CounterEvent::Decremented { counter, amount: 6, value: -1 },
+            CounterEvent::Set { counter, old: -1, value: 7 },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_return_data() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            caller_instruction(&env.user.pubkey(), 0, 0),
+            caller_instruction(&env.user.pubkey(), 1, 1),
+            caller_instruction(&env.user.pubkey(), 1, 2),
+            caller_instruction(&env.user.pubkey(), 2, 0),
+            caller_instruction(&env.user.pubkey(), 0, 0),
+        ],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[caller_instruction(&env.user.pubkey(), 0, 1)],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::InvalidArgument)
+    );
+
+    // an admin overwriting the value gets the counter back too
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::set(
+            &env.user.pubkey(),
+            &DEFAULT_NAMESPACE,
+            &[env.admin.pubkey()],
+            7,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    let simulation = env.ctx.banks_client.simulate_transaction(tx).await.unwrap();
+    assert_eq!(simulation.result, Some(Ok(())));
+    let mut return_data = simulation.simulation_details.unwrap().return_data.unwrap();
+    assert_eq!(return_data.program_id, id());
+    // the runtime trims the trailing zeros of the return data
+    return_data.data.resize(Counter::LEN, 0);
+    let counter = Counter::unpack(&return_data.data).unwrap();
+    assert_eq!(counter.value, 7);
+}
+
+#[tokio::test]
+async fn test_cpi_with_fee() {
+    let mut env = Env::new().await;
+    let treasury_pubkey = Settings::get_treasury_pubkey(&DEFAULT_NAMESPACE);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::set_fee(
+                &DEFAULT_NAMESPACE,
+                &env.admin.pubkey(),
+                &[env.admin.pubkey()],
+                500,
+            ),
+            caller_instruction(&env.user.pubkey(), 1, 1),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+    assert_eq!(
+        env.ctx.banks_client.get_balance(treasury_pubkey).await.unwrap(),
+        rent.minimum_balance(0) + 500
+    );
+}
+
+#[tokio::test]
+async fn test_history() {
+    let mut env = Env::new().await;
+    let counter_pubkey = Counter::get_counter_pubkey(&env.user.pubkey());
+    let history_pubkey = History::get_history_pubkey(&counter_pubkey);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::create_history(&env.user.pubkey(), COUNTER_SEED, 0)],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::InvalidHistoryCapacity as u32)
+        )
+    );
+
+    // 4 changes overwrite the oldest entry of 3
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::create_history(&env.user.pubkey(), COUNTER_SEED, 3),
+            CounterInstruction::batch(
+                &env.user.pubkey(),
+                &DEFAULT_NAMESPACE,
+                vec![CounterOp::Inc, CounterOp::Add { amount: 5 }, CounterOp::Dec],
+            ),
+            CounterInstruction::sub(&env.user.pubkey(), &DEFAULT_NAMESPACE, 3),
+        ],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().unwrap();
+    let counter = Counter::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(counter.history, history_pubkey.to_bytes());
+    assert_eq!(counter.value, 1);
+
+    let acc = env.ctx.banks_client.get_account(history_pubkey).await.unwrap().unwrap();
+    assert_eq!(acc.data.len(), History::space(3));
+    let (history, entries) = History::decode(&acc.data).unwrap();
+    assert_eq!(history.counter, counter_pubkey.to_bytes());
+    assert_eq!(history.total, 4);
+    assert_eq!(entries.iter().map(|e| e.delta).collect::<Vec<_>>(), [5, -2, -3]);
+    assert!(entries.iter().all(|e| e.signer == env.user.pubkey().to_bytes()));
+    assert!(entries.iter().all(|e| e.slot == counter.last_update_slot));
+
+    // an admin overwriting the value is recorded as well
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::set(
+            &env.user.pubkey(),
+            &DEFAULT_NAMESPACE,
+            &[env.admin.pubkey()],
+            10,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env.ctx.banks_client.get_account(history_pubkey).await.unwrap().unwrap();
+    let (history, entries) = History::decode(&acc.data).unwrap();
+    assert_eq!(history.total, 5);
+    assert_eq!(entries.iter().map(|e| e.delta).collect::<Vec<_>>(), [-2, -3, 9]);
+    assert_eq!(entries[2].signer, env.admin.pubkey().to_bytes());
+
+    // the history can't be left out
+    let mut ix = CounterInstruction::inc(&env.user.pubkey(), &DEFAULT_NAMESPACE);
+    ix.accounts.remove(5);
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::HistoryRequired as u32)
+        )
+    );
+
+    // the history is closed along with the counter
+    let history_lamports = acc.lamports;
+    let admin_lamports = env.ctx.banks_client.get_balance(env.admin.pubkey()).await.unwrap();
+    let counter_lamports =
+        env.ctx.banks_client.get_account(counter_pubkey).await.unwrap().unwrap().lamports;
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::close_counter(
+            &env.user.pubkey(),
+            &DEFAULT_NAMESPACE,
+            &env.admin.pubkey(),
+        )],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    assert!(env.ctx.banks_client.get_account(history_pubkey).await.unwrap().is_none());
+    assert_eq!(
+        env.ctx.banks_client.get_balance(env.admin.pubkey()).await.unwrap(),
+        admin_lamports + counter_lamports + history_lamports
+    );
+}
+
+async fn get_stats(ctx: &mut ProgramTestContext) -> Stats {
+    let acc = ctx.banks_client.get_account(Stats::get_stats_pubkey()).await.unwrap().unwrap();
+    Stats::unpack(acc.data.as_slice()).unwrap()
+}
+
+#[tokio::test]
+async fn test_stats() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::initialize_stats(&env.user.pubkey(), &[env.user.pubkey()])],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::AdminRequired as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::initialize_stats(&env.admin.pubkey(), &[env.admin.pubkey()]),
+            CounterInstruction::create_named_counter(
+                &env.user.pubkey(),
+                "apples",
+                &DEFAULT_NAMESPACE,
+            ),
+            CounterInstruction::batch_named(
+                &env.user.pubkey(),
+                "apples",
+                &DEFAULT_NAMESPACE,
+                vec![CounterOp::Inc, CounterOp::Add { amount: 5 }, CounterOp::Sub { amount: 9 }],
+            ),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let stats = get_stats(&mut env.ctx).await;
+    assert_eq!(stats.total_ops, 3);
+    assert_eq!(stats.counters, 1);
+    assert_eq!(stats.value_sum, -3);
+    assert_eq!((stats.min_value, stats.max_value), (-3, 6));
+
+    // counted counters and new ones can't leave the stats out, they would go stale
+    let user = env.user.pubkey();
+    let mut create_ix =
+        CounterInstruction::create_named_counter(&user, "pears", &DEFAULT_NAMESPACE);
+    create_ix.accounts.remove(4);
+    let mut inc_ix = CounterInstruction::inc_named(&user, "apples", &DEFAULT_NAMESPACE);
+    inc_ix.accounts.remove(6);
+    let mut close_ix =
+        CounterInstruction::close_named_counter(&user, "apples", &DEFAULT_NAMESPACE, &user);
+    close_ix.accounts.remove(4);
+    for ix in [create_ix, inc_ix, close_ix] {
+        let tx = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&user),
+            &[&env.user],
+            env.ctx.last_blockhash,
+        );
+        assert_eq!(
+            env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(CounterError::StatsRequired as u32)
+            )
+        );
+    }
+
+    // a counter created before the stats may leave them out, its change isn't counted then
+    let mut inc_ix = CounterInstruction::inc(&user, &DEFAULT_NAMESPACE);
+    inc_ix.accounts.remove(6);
+    let tx = Transaction::new_signed_with_payer(
+        &[inc_ix],
+        Some(&user),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(get_stats(&mut env.ctx).await, stats);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::set_named(
+                &env.user.pubkey(),
+                "apples",
+                &DEFAULT_NAMESPACE,
+                &[env.admin.pubkey()],
+                10,
+            ),
+            CounterInstruction::close_named_counter(
+                &env.user.pubkey(),
+                "apples",
+                &DEFAULT_NAMESPACE,
+                &env.user.pubkey(),
+            ),
+        ],
+        Some(&env.admin.pubkey()),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let stats = get_stats(&mut env.ctx).await;
+    assert_eq!(stats.total_ops, 4);
+    assert_eq!(stats.counters, 0);
+    assert_eq!(stats.value_sum, 0);
+    assert_eq!((stats.min_value, stats.max_value), (-3, 10));
+
+    // only the admins reset the stats
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::reset_stats(&[env.user.pubkey()])],
+        Some(&env.user.pubkey()),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert!(env.ctx.banks_client.process_transaction(tx).await.is_err());
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::reset_stats(&[env.admin.pubkey()])],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(get_stats(&mut env.ctx).await, Stats::default());
+}
+
+#[tokio::test]
+async fn test_stats_older_counters() {
+    let mut env = Env::new().await;
+    let admin = env.admin.pubkey();
+    let user = env.user.pubkey();
+
+    // a counter of the first version of the program
+    let legacy_counter = vec![3, 0, 0, 0, 10, 0, 0, 0, 0, 0, 0, 0];
+    let legacy_pubkey = Counter::get_legacy_counter_pubkey(&admin, COUNTER_SEED);
+    env.ctx.set_account(
+        &legacy_pubkey,
+        &Account {
+            lamports: Rent::default().minimum_balance(legacy_counter.len()),
+            data: legacy_counter,
+            owner: id(),
+            ..Account::default()
+        }
+        .into(),
+    );
+
+    // the counter of the user was created before the stats
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::initialize_stats(&admin, &[admin]),
+            CounterInstruction::create_named_counter(&user, "apples", &DEFAULT_NAMESPACE),
+            CounterInstruction::inc_named(&user, "apples", &DEFAULT_NAMESPACE),
+            CounterInstruction::migrate(&admin, &legacy_pubkey),
+            CounterInstruction::inc(&user, &DEFAULT_NAMESPACE),
+        ],
+        Some(&admin),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env.ctx.banks_client.get_account(legacy_pubkey).await.unwrap().unwrap();
+    assert!(!Counter::unpack(acc.data.as_slice()).unwrap().in_stats);
+    let stats = get_stats(&mut env.ctx).await;
+    assert_eq!(stats.total_ops, 2);
+    assert_eq!(stats.counters, 1);
+    assert_eq!(stats.value_sum, 1);
+
+    // closing counters the stats never counted leaves them alone
+    let mut close_legacy_ix = CounterInstruction::close_counter(&admin, &DEFAULT_NAMESPACE, &admin);
+    close_legacy_ix.accounts[1].pubkey = legacy_pubkey;
+    let tx = Transaction::new_signed_with_payer(
+        &[close_legacy_ix, CounterInstruction::close_counter(&user, &DEFAULT_NAMESPACE, &user)],
+        Some(&admin),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    assert!(env.ctx.banks_client.get_account(legacy_pubkey).await.unwrap().is_none());
+    let stats = get_stats(&mut env.ctx).await;
+    assert_eq!(stats.counters, 1);
+    assert_eq!(stats.value_sum, 1);
+}
+
+#[tokio::test]
+async fn test_foreign_stats_and_leaderboard() {
+    let mut env = Env::new().await;
+    let admin = env.admin.pubkey();
+    let user = env.user.pubkey();
+    let stats_pubkey = Stats::get_stats_pubkey();
+    let leaderboard_pubkey = Leaderboard::get_leaderboard_pubkey(&DEFAULT_NAMESPACE);
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+
+    // a pre-funded stats address is not initialized yet
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::transfer(&admin, &stats_pubkey, rent.minimum_balance(0)),
+            CounterInstruction::create_named_counter(&user, "apples", &DEFAULT_NAMESPACE),
+        ],
+        Some(&admin),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // accounts at the stats and leaderboard addresses that aren't the program's
+    for pubkey in [&stats_pubkey, &leaderboard_pubkey] {
+        env.ctx.set_account(
+            pubkey,
+            &Account {
+                lamports: rent.minimum_balance(Stats::LEN),
+                data: vec![0; Stats::LEN],
+                owner: Pubkey::new_unique(),
+                ..Account::default()
+            }
+            .into(),
+        );
+    }
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::create_counter(&admin, &DEFAULT_NAMESPACE)],
+        Some(&admin),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+
+    // as if the counter changed along with a leaderboard, so closing it needs one
+    let apples = Counter::get_named_counter_pubkey(&user, "apples");
+    let mut acc = env.ctx.banks_client.get_account(apples).await.unwrap().unwrap();
+    let mut counter = Counter::unpack(acc.data.as_slice()).unwrap();
+    counter.ranked = true;
+    acc.data = counter.try_to_vec().unwrap();
+    env.ctx.set_account(&apples, &acc.into());
+
+    // without the stats, the foreign leaderboard still fails
+    let mut close_ix =
+        CounterInstruction::close_named_counter(&user, "apples", &DEFAULT_NAMESPACE, &user);
+    let stats_meta = close_ix.accounts.iter_mut().find(|meta| meta.pubkey == stats_pubkey);
+    stats_meta.unwrap().pubkey = Pubkey::new_unique();
+    let tx = Transaction::new_signed_with_payer(
+        &[close_ix],
+        Some(&admin),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+
+    // with an empty stats address, the foreign leaderboard still fails
+    env.ctx.set_account(&stats_pubkey, &Account::default().into());
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::close_named_counter(&user, "apples", &DEFAULT_NAMESPACE, &user)],
+        Some(&admin),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+}
+
+async fn get_leaderboard(ctx: &mut ProgramTestContext) -> Vec<LeaderboardEntry> {
+    let leaderboard_pubkey = Leaderboard::get_leaderboard_pubkey(&DEFAULT_NAMESPACE);
+    let acc = ctx.banks_client.get_account(leaderboard_pubkey).await.unwrap().unwrap();
+    Leaderboard::unpack(acc.data.as_slice()).unwrap().entries().to_vec()
+}
+
+#[tokio::test]
+async fn test_leaderboard() {
+    let mut env = Env::new().await;
+    let user = env.user.pubkey();
+    let admin = env.admin.pubkey();
+    let other = Keypair::new();
+    let counter = Counter::get_counter_pubkey(&user);
+    let apples = Counter::get_named_counter_pubkey(&user, "apples");
+    let admin_counter = Counter::get_counter_pubkey(&admin);
+    let other_counter = Counter::get_counter_pubkey(&other.pubkey());
+    let entry = |counter: &Pubkey, owner: &Pubkey, value| LeaderboardEntry {
+        counter: counter.to_bytes(),
+        owner: owner.to_bytes(),
+        value,
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::set_leaderboard_size(&DEFAULT_NAMESPACE, &admin, &[admin], 33)],
+        Some(&admin),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::InvalidLeaderboardSize as u32)
+        )
+    );
+
+    // an owner has a single entry, with its counter changed last
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::set_leaderboard_size(&DEFAULT_NAMESPACE, &admin, &[admin], 2),
+            CounterInstruction::create_counter(&admin, &DEFAULT_NAMESPACE),
+            CounterInstruction::create_named_counter(&user, "apples", &DEFAULT_NAMESPACE),
+            CounterInstruction::add_named(&user, "apples", &DEFAULT_NAMESPACE, 5),
+            CounterInstruction::add(&user, &DEFAULT_NAMESPACE, 3),
+        ],
+        Some(&admin),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(get_leaderboard(&mut env.ctx).await, [entry(&counter, &user, 3)]);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::add_named(&user, "apples", &DEFAULT_NAMESPACE, 1),
+            CounterInstruction::add(&admin, &DEFAULT_NAMESPACE, 6),
+        ],
+        Some(&admin),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(
+        get_leaderboard(&mut env.ctx).await,
+        [entry(&apples, &user, 6), entry(&admin_counter, &admin, 6)]
+    );
+
+    // a tie with the lowest entry doesn't qualify, a higher value evicts it
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::transfer(&admin, &other.pubkey(), 100_000_000),
+            CounterInstruction::create_counter(&other.pubkey(), &DEFAULT_NAMESPACE),
+            CounterInstruction::add(&other.pubkey(), &DEFAULT_NAMESPACE, 6),
+        ],
+        Some(&admin),
+        &[&env.admin, &other],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(
+        get_leaderboard(&mut env.ctx).await,
+        [entry(&apples, &user, 6), entry(&admin_counter, &admin, 6)]
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::inc(&other.pubkey(), &DEFAULT_NAMESPACE)],
+        Some(&admin),
+        &[&env.admin, &other],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(
+        get_leaderboard(&mut env.ctx).await,
+        [entry(&other_counter, &other.pubkey(), 7), entry(&apples, &user, 6)]
+    );
+
+    // an owner whose counter changed last drops, isn't replaced by an owner off the board
+    // until that one changes a counter again
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::inc(&user, &DEFAULT_NAMESPACE)],
+        Some(&user),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(
+        get_leaderboard(&mut env.ctx).await,
+        [entry(&other_counter, &other.pubkey(), 7), entry(&counter, &user, 4)]
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::inc(&admin, &DEFAULT_NAMESPACE)],
+        Some(&admin),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(
+        get_leaderboard(&mut env.ctx).await,
+        [entry(&other_counter, &other.pubkey(), 7), entry(&admin_counter, &admin, 7)]
+    );
+
+    // the leaderboard can't be left out
+    let mut ix = CounterInstruction::inc(&user, &DEFAULT_NAMESPACE);
+    ix.accounts.remove(7);
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&user),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::LeaderboardRequired as u32)
+        )
+    );
+
+    // nor when closing a counter that changed along with it
+    let mut ix = CounterInstruction::close_counter(&admin, &DEFAULT_NAMESPACE, &admin);
+    ix.accounts.remove(5);
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&admin),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    assert_eq!(
+        env.ctx.banks_client.process_transaction(tx).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(CounterError::LeaderboardRequired as u32)
+        )
+    );
+
+    // a counter that never changed along with it closes without it
+    let mut ix = CounterInstruction::close_named_counter(&user, "pears", &DEFAULT_NAMESPACE, &user);
+    ix.accounts.remove(5);
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::create_named_counter(&user, "pears", &DEFAULT_NAMESPACE), ix],
+        Some(&user),
+        &[&env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // a closed counter leaves the board
+    let tx = Transaction::new_signed_with_payer(
+        &[CounterInstruction::close_counter(&admin, &DEFAULT_NAMESPACE, &admin)],
+        Some(&admin),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(get_leaderboard(&mut env.ctx).await, [entry(&other_counter, &other.pubkey(), 7)]);
+
+    // a smaller board drops the lowest entries
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            CounterInstruction::inc(&user, &DEFAULT_NAMESPACE),
+            CounterInstruction::set_leaderboard_size(&DEFAULT_NAMESPACE, &admin, &[admin], 1),
+        ],
+        Some(&admin),
+        &[&env.admin, &env.user],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    assert_eq!(get_leaderboard(&mut env.ctx).await, [entry(&other_counter, &other.pubkey(), 7)]);
 }